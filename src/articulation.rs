@@ -0,0 +1,132 @@
+//! Articulations that move the pitch of a note while it rings: hammer-ons, pull-offs,
+//! slides, bends, vibrato and MIDI pitch bend. Also the techniques a note can be played
+//! with, which change its tone rather than its pitch.
+
+use std::f64::consts::TAU;
+
+// How long vibrato takes to reach its full depth once it starts
+const VIBRATO_ATTACK: f64 = 0.3;
+
+/// A change of pitch during a note. Times are in seconds from the start of the note and
+/// pitch changes in semitones of equal temperament, relative to the pitch reached by
+/// the articulations before.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Articulation {
+    /// Fret a higher note on the ringing string, which taps it lightly.
+    HammerOn { time: f64, semitones: f64 },
+    /// Lift a finger off to a lower note, plucking the string a little.
+    PullOff { time: f64, semitones: f64 },
+    /// Slide fret by fret to another note over `length` seconds.
+    Slide {
+        time: f64,
+        length: f64,
+        semitones: f64,
+    },
+    /// Bend the string smoothly over `length` seconds. The progress of the bend is raised
+    /// to the power of `curve`, so values below 1 rise quickly and then settle.
+    Bend {
+        time: f64,
+        length: f64,
+        semitones: f64,
+        curve: f64,
+    },
+    /// Vibrato from `delay` on, fading in. Like pushing a string, it only raises the
+    /// pitch, by up to `depth` semitones `rate` times per second.
+    Vibrato { delay: f64, rate: f64, depth: f64 },
+    /// A pitch bend controller value from `time` on. Unlike the other changes it is not
+    /// added up: the latest one applies.
+    PitchBend { time: f64, semitones: f64 },
+}
+
+impl Articulation {
+    /// Start time in seconds.
+    pub fn time(&self) -> f64 {
+        match *self {
+            Articulation::HammerOn { time, .. }
+            | Articulation::PullOff { time, .. }
+            | Articulation::Slide { time, .. }
+            | Articulation::Bend { time, .. }
+            | Articulation::PitchBend { time, .. } => time,
+            Articulation::Vibrato { delay, .. } => delay,
+        }
+    }
+
+    // Pitch change in semitones that this articulation adds at time `t`
+    fn semitones_at(&self, t: f64) -> f64 {
+        if t < self.time() {
+            return 0.0;
+        }
+        match *self {
+            Articulation::HammerOn { semitones, .. } | Articulation::PullOff { semitones, .. } => {
+                semitones
+            }
+            Articulation::Slide {
+                time,
+                length,
+                semitones,
+            } => {
+                // Whole frets at a time, reaching the target at the end
+                let progress = progress(t - time, length);
+                if progress >= 1.0 {
+                    semitones
+                } else {
+                    (semitones * progress).trunc()
+                }
+            }
+            Articulation::Bend {
+                time,
+                length,
+                semitones,
+                curve,
+            } => semitones * progress(t - time, length).powf(curve),
+            Articulation::Vibrato { delay, rate, depth } => {
+                let t = t - delay;
+                let fade = progress(t, VIBRATO_ATTACK);
+                depth * fade * 0.5 * (1.0 - (TAU * rate * t).cos())
+            }
+            Articulation::PitchBend { .. } => 0.0,
+        }
+    }
+}
+
+/// How the string is played.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Technique {
+    #[default]
+    Open,
+    /// The side of the picking hand rests on the strings by the bridge, so the note dies
+    /// away quickly and sounds darker.
+    PalmMute,
+    /// A finger lightly touches the string over a node, leaving only the partials at
+    /// multiples of `multiple` times the note frequency. Pure and bell-like.
+    NaturalHarmonic { multiple: u32 },
+    /// The picking hand touches the node while it plucks, giving a brighter and thinner
+    /// harmonic than a natural one.
+    ArtificialHarmonic { multiple: u32 },
+}
+
+/// Pitch offset in semitones at time `t` of a note with these articulations.
+pub fn pitch_offset(articulations: &[Articulation], t: f64) -> f64 {
+    let mut pitch_bend = 0.0;
+    let mut bend_time = f64::NEG_INFINITY;
+    let mut offset = 0.0;
+    for articulation in articulations {
+        match *articulation {
+            Articulation::PitchBend { time, semitones } if time <= t && time >= bend_time => {
+                pitch_bend = semitones;
+                bend_time = time;
+            }
+            _ => offset += articulation.semitones_at(t),
+        }
+    }
+    offset + pitch_bend
+}
+
+// How far through a change of `length` seconds we are after `elapsed` seconds, in 0...1
+fn progress(elapsed: f64, length: f64) -> f64 {
+    if length <= 0.0 {
+        1.0
+    } else {
+        (elapsed / length).clamp(0.0, 1.0)
+    }
+}
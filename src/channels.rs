@@ -0,0 +1,143 @@
+//! Mapping the stereo graph output onto the channels of an output device.
+
+use clap::ValueEnum;
+
+/// What surround devices get on the channels that have no counterpart in the graph.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Upmix {
+    /// Only the front left and right speakers play; center, LFE and surrounds stay silent.
+    #[default]
+    Silent,
+    /// Center and surrounds get attenuated copies and the LFE a lowpassed mono sum.
+    Derived,
+}
+
+/// Speaker layouts by channel count, in the usual WAVE/ALSA channel order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Layout {
+    Mono,
+    Stereo,
+    /// FL FR BL BR
+    Quad,
+    /// FL FR FC LFE BL BR
+    Surround51,
+    /// FL FR FC LFE BL BR SL SR
+    Surround71,
+    /// Unknown layout: front pair first, everything else silent.
+    Other,
+}
+
+impl Layout {
+    fn from_channels(channels: usize) -> Self {
+        match channels {
+            1 => Layout::Mono,
+            2 => Layout::Stereo,
+            4 => Layout::Quad,
+            6 => Layout::Surround51,
+            8 => Layout::Surround71,
+            _ => Layout::Other,
+        }
+    }
+}
+
+// Crossover frequency of the derived LFE channel
+const LFE_CUTOFF: f64 = 120.0;
+
+// -3 dB, for the derived center channel
+const CENTER_GAIN: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+// -6 dB, for the derived surround channels
+const SURROUND_GAIN: f32 = 0.5;
+
+/// Maps frames from the graph to the channel layout of the output device.
+pub struct ChannelMap {
+    layout: Layout,
+    upmix: Upmix,
+    frame: Vec<f32>,
+    lfe_coefficient: f32,
+    lfe_state: f32,
+}
+
+impl ChannelMap {
+    pub fn new(device_channels: usize, sample_rate: f64, upmix: Upmix) -> Self {
+        ChannelMap {
+            layout: Layout::from_channels(device_channels),
+            upmix,
+            frame: vec![0.0; device_channels],
+            lfe_coefficient: (1.0 - (-std::f64::consts::TAU * LFE_CUTOFF / sample_rate).exp())
+                as f32,
+            lfe_state: 0.0,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.frame.len()
+    }
+
+    /// Map one graph frame with any number of channels to a device frame. Graphs with the
+    /// same channel count as the device pass through; anything else goes through stereo.
+    pub fn map(&mut self, input: &[f32]) -> &[f32] {
+        if input.len() == self.frame.len() {
+            self.frame.copy_from_slice(input);
+            return &self.frame;
+        }
+
+        let (left, right) = fold_to_stereo(input);
+        let mid = (left + right) * 0.5;
+        self.lfe_state += (mid - self.lfe_state) * self.lfe_coefficient;
+        let derived = self.upmix == Upmix::Derived;
+        let (center, lfe) = if derived {
+            (mid * CENTER_GAIN, self.lfe_state)
+        } else {
+            (0.0, 0.0)
+        };
+        let (back_left, back_right) = if derived {
+            (left * SURROUND_GAIN, right * SURROUND_GAIN)
+        } else {
+            (0.0, 0.0)
+        };
+
+        self.frame.fill(0.0);
+        match self.layout {
+            Layout::Mono => self.frame[0] = mid,
+            Layout::Stereo | Layout::Other => {
+                self.frame[0] = left;
+                if let Some(x) = self.frame.get_mut(1) {
+                    *x = right;
+                }
+            }
+            Layout::Quad => self
+                .frame
+                .copy_from_slice(&[left, right, back_left, back_right]),
+            Layout::Surround51 => {
+                self.frame
+                    .copy_from_slice(&[left, right, center, lfe, back_left, back_right]);
+            }
+            Layout::Surround71 => {
+                self.frame.copy_from_slice(&[
+                    left, right, center, lfe, back_left, back_right, back_left, back_right,
+                ]);
+            }
+        }
+        &self.frame
+    }
+}
+
+// Mix any number of channels down to stereo: mono is duplicated,
+// otherwise even channels go left and odd channels go right
+fn fold_to_stereo(input: &[f32]) -> (f32, f32) {
+    match input {
+        [] => (0.0, 0.0),
+        [mono] => (*mono, *mono),
+        [left, right] => (*left, *right),
+        _ => {
+            let left = input.iter().step_by(2);
+            let right = input.iter().skip(1).step_by(2);
+            let (left_count, right_count) = (left.len() as f32, right.len() as f32);
+            (
+                left.sum::<f32>() / left_count,
+                right.sum::<f32>() / right_count,
+            )
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Chord symbols, guitar-style voicings and the strums and arpeggios that play them.
+
+use crate::score::parse_note_name;
+use anyhow::{Context, bail};
+
+// Chord tones in semitones above the root, by suffix
+const QUALITIES: &[(&str, &[i32])] = &[
+    ("", &[0, 4, 7]),
+    ("maj", &[0, 4, 7]),
+    ("m", &[0, 3, 7]),
+    ("min", &[0, 3, 7]),
+    ("-", &[0, 3, 7]),
+    ("5", &[0, 7]),
+    ("6", &[0, 4, 7, 9]),
+    ("m6", &[0, 3, 7, 9]),
+    ("7", &[0, 4, 7, 10]),
+    ("maj7", &[0, 4, 7, 11]),
+    ("M7", &[0, 4, 7, 11]),
+    ("m7", &[0, 3, 7, 10]),
+    ("min7", &[0, 3, 7, 10]),
+    ("mmaj7", &[0, 3, 7, 11]),
+    ("m7b5", &[0, 3, 6, 10]),
+    ("dim", &[0, 3, 6]),
+    ("dim7", &[0, 3, 6, 9]),
+    ("aug", &[0, 4, 8]),
+    ("+", &[0, 4, 8]),
+    ("sus", &[0, 5, 7]),
+    ("sus2", &[0, 2, 7]),
+    ("sus4", &[0, 5, 7]),
+    ("7sus4", &[0, 5, 7, 10]),
+    ("add9", &[0, 4, 7, 14]),
+    ("9", &[0, 4, 7, 10, 14]),
+    ("maj9", &[0, 4, 7, 11, 14]),
+    ("m9", &[0, 3, 7, 10, 14]),
+];
+
+// Voicings span roughly the range of a guitar in standard tuning, E2 to E5
+const LOWEST_NOTE: i32 = 40;
+const HIGHEST_NOTE: i32 = 76;
+
+// As many notes as a guitar has strings
+const VOICING_SIZE: usize = 6;
+
+// Smallest gap in semitones between neighbouring notes of a voicing
+const MIN_INTERVAL: i32 = 2;
+
+/// A chord symbol such as `Cmaj7`, `Am`, `G7/B` or `Dsus4`. Note letters may be lowercase,
+/// as in `am`, but chord qualities keep their case, so `M7` differs from `m7`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chord {
+    /// Pitch class of the root, 0 for C.
+    pub root: i32,
+    /// Chord tones in semitones above the root, starting with 0.
+    pub intervals: Vec<i32>,
+    /// Pitch class of a different bass note, from a slash chord.
+    pub bass: Option<i32>,
+}
+
+impl Chord {
+    pub fn parse(symbol: &str) -> Result<Chord, anyhow::Error> {
+        let (name, bass) = match symbol.split_once('/') {
+            Some((name, bass)) => (name, Some(bass)),
+            None => (symbol, None),
+        };
+        let (root, suffix) =
+            parse_pitch_class(name).with_context(|| format!("invalid chord '{symbol}'"))?;
+        let Some(&(_, intervals)) = QUALITIES.iter().find(|(s, _)| *s == suffix) else {
+            bail!("unknown chord quality '{suffix}' in '{symbol}'");
+        };
+        let bass = match bass {
+            Some(bass) => match parse_pitch_class(bass) {
+                Ok((pitch_class, "")) => Some(pitch_class),
+                _ => bail!("invalid bass note '{bass}' in '{symbol}'"),
+            },
+            None => None,
+        };
+        Ok(Chord {
+            root,
+            intervals: intervals.to_vec(),
+            bass,
+        })
+    }
+
+    /// MIDI notes of an open, guitar-like voicing from low to high: the bass note in the
+    /// lowest octave, then the chord tones stacked upwards in turn, up to six notes.
+    pub fn voicing(&self) -> Vec<u8> {
+        let bass_class = self.bass.unwrap_or(self.root);
+        let bass = LOWEST_NOTE + (bass_class - LOWEST_NOTE).rem_euclid(12);
+        let mut notes = vec![bass];
+
+        // Go through the chord tones from the root up, skipping the bass note once
+        let tones: Vec<i32> = self
+            .intervals
+            .iter()
+            .map(|interval| (self.root + interval).rem_euclid(12))
+            .filter(|&tone| tone != bass_class || self.bass.is_none())
+            .collect();
+        let start = if self.bass.is_none() { 1 } else { 0 };
+        let candidates = tones
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(tones.len() * VOICING_SIZE);
+        for &tone in candidates {
+            let previous = *notes.last().expect("voicing starts with the bass");
+            let next = previous + 1 + (tone - previous - 1).rem_euclid(12);
+            // Skip tones a semitone above the last one, as a guitar voicing would
+            if next - previous < MIN_INTERVAL {
+                continue;
+            }
+            if next > HIGHEST_NOTE || notes.len() == VOICING_SIZE {
+                break;
+            }
+            notes.push(next);
+        }
+        notes.into_iter().map(|note| note as u8).collect()
+    }
+}
+
+// Parse a note letter with accidentals and return the pitch class and the rest
+fn parse_pitch_class(s: &str) -> Result<(i32, &str), anyhow::Error> {
+    let (semitone, rest) = parse_note_name(s)?;
+    Ok((semitone.rem_euclid(12), rest))
+}
+
+/// Which way the pick moves across the strings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StrumDirection {
+    /// From the lowest string to the highest.
+    #[default]
+    Down,
+    /// From the highest string to the lowest.
+    Up,
+}
+
+/// How a chord is strummed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Strum {
+    /// Time in seconds between neighbouring strings.
+    pub delay: f64,
+    pub direction: StrumDirection,
+}
+
+impl Default for Strum {
+    fn default() -> Self {
+        Strum {
+            delay: 0.015,
+            direction: StrumDirection::Down,
+        }
+    }
+}
+
+impl Strum {
+    /// Start offset in seconds of each note of a voicing, in voicing order.
+    pub fn offsets(&self, notes: usize) -> Vec<f64> {
+        (0..notes)
+            .map(|i| match self.direction {
+                StrumDirection::Down => i,
+                StrumDirection::Up => notes - 1 - i,
+            })
+            .map(|step| step as f64 * self.delay)
+            .collect()
+    }
+}
+
+/// An arpeggio pattern: indices into the voicing from the lowest note, played one per
+/// step and repeated for as long as the chord lasts. Indices wrap around the voicing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arpeggio {
+    pub pattern: Vec<usize>,
+    /// Length of a step in beats.
+    pub step: f64,
+}
+
+impl Default for Arpeggio {
+    /// Eighth notes up from the bass and back down.
+    fn default() -> Self {
+        Arpeggio {
+            pattern: vec![0, 2, 3, 4, 5, 4, 3, 2],
+            step: 0.5,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voicing(symbol: &str) -> Vec<u8> {
+        Chord::parse(symbol).expect("valid chord").voicing()
+    }
+
+    #[test]
+    fn symbols() {
+        let chord = Chord::parse("F#m7/E").unwrap();
+        assert_eq!(chord.root, 6);
+        assert_eq!(chord.intervals, [0, 3, 7, 10]);
+        assert_eq!(chord.bass, Some(4));
+        assert_eq!(Chord::parse("Bb").unwrap().root, 10);
+        assert!(Chord::parse("Cxyz").is_err());
+        assert!(Chord::parse("H").is_err());
+        assert_eq!(Chord::parse("am").unwrap(), Chord::parse("Am").unwrap());
+        assert_eq!(
+            Chord::parse("bbm7/f").unwrap(),
+            Chord::parse("Bbm7/F").unwrap()
+        );
+        assert_eq!(Chord::parse("Cb").unwrap().root, 11);
+        assert!(Chord::parse("C/X").is_err());
+    }
+
+    #[test]
+    fn voicings_stack_up_from_the_bass() {
+        assert_eq!(voicing("C"), [48, 52, 55, 60, 64, 67]);
+        assert_eq!(voicing("Am"), [45, 48, 52, 57, 60, 64]);
+        assert_eq!(voicing("E"), [40, 44, 47, 52, 56, 59]);
+        assert_eq!(voicing("Cmaj7"), [48, 52, 55, 59, 64, 67]);
+        // The third in the bass is not repeated straight above it
+        assert_eq!(voicing("G7/B"), [47, 55, 62, 65, 67, 74]);
+    }
+
+    #[test]
+    fn voicings_stay_on_the_guitar() {
+        for &(suffix, _) in QUALITIES {
+            for root in ["C", "F#", "Bb"] {
+                let notes = voicing(&format!("{root}{suffix}"));
+                assert!(!notes.is_empty() && notes.len() <= VOICING_SIZE);
+                assert!(
+                    notes
+                        .windows(2)
+                        .all(|pair| pair[1] - pair[0] >= MIN_INTERVAL as u8)
+                );
+                assert!(
+                    notes
+                        .iter()
+                        .all(|&note| (LOWEST_NOTE..=HIGHEST_NOTE).contains(&(note as i32)))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn strums_run_across_the_strings() {
+        let down = Strum::default().offsets(3);
+        assert_eq!(down, [0.0, 0.015, 0.03]);
+        let up = Strum {
+            delay: 0.01,
+            direction: StrumDirection::Up,
+        };
+        assert_eq!(up.offsets(3), [0.02, 0.01, 0.0]);
+    }
+}
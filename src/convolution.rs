@@ -0,0 +1,492 @@
+//! Convolution with impulse responses loaded from WAV files, partitioned into FFT blocks
+//! so that long responses run in real time with a short, fixed latency.
+//!
+//! Like the other nodes in this crate that keep buffers, the convolvers allocate them
+//! when the sample rate is set, so ticking never allocates and they are safe to run in
+//! the audio callback.
+
+use anyhow::{Context, bail};
+use fundsp::fft::{inverse_fft, real_fft};
+use fundsp::hacker::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Length of the first partitions in samples, which is also the latency
+const BLOCK: usize = 64;
+
+// Later parts of the response are convolved in partitions this many times longer,
+// up to the longest one, so long tails cost little more than short ones
+const STAGE_GROWTH: usize = 8;
+const MAX_BLOCK: usize = 4096;
+
+/// An impulse response read from a WAV file, with one or more channels. In presets it is
+/// stored as the path of the file.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "PathBuf", into = "PathBuf")]
+pub struct ImpulseResponse {
+    path: PathBuf,
+    sample_rate: f64,
+    channels: Arc<Vec<Vec<f32>>>,
+    // Kernels transformed so far, shared with every clone
+    kernels: Arc<Mutex<Vec<Arc<Kernel>>>>,
+}
+
+impl ImpulseResponse {
+    // Stands in until a file is given, with no samples at all. It serializes as an empty
+    // path, which does not read back, and convolves to silence.
+    pub(crate) fn missing() -> Self {
+        ImpulseResponse {
+            path: PathBuf::new(),
+            sample_rate: DEFAULT_SR,
+            channels: Arc::default(),
+            kernels: Arc::default(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let wave = Wave::load(path)
+            .with_context(|| format!("Could not read impulse response {}", path.display()))?;
+        let channels = (0..wave.channels())
+            .map(|channel| wave.channel(channel).clone())
+            .collect();
+        ImpulseResponse::from_samples(wave.sample_rate(), channels)
+            .map(|response| response.with_path(path))
+            .with_context(|| format!("Invalid impulse response {}", path.display()))
+    }
+
+    /// An impulse response from samples at `sample_rate`, one `Vec` per channel.
+    pub fn from_samples(sample_rate: f64, channels: Vec<Vec<f32>>) -> Result<Self, anyhow::Error> {
+        if channels.is_empty() || channels.iter().all(|samples| samples.is_empty()) {
+            bail!("the impulse response is empty");
+        }
+        if !(sample_rate.is_finite() && sample_rate > 0.0) {
+            bail!("invalid sample rate {sample_rate}");
+        }
+        Ok(ImpulseResponse {
+            path: PathBuf::new(),
+            sample_rate,
+            channels: Arc::new(channels),
+            kernels: Arc::default(),
+        })
+    }
+
+    fn with_path(mut self, path: &Path) -> Self {
+        self.path = path.to_path_buf();
+        self
+    }
+
+    /// File the response was loaded from, empty if it was made from samples.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Length in seconds.
+    pub fn duration(&self) -> f64 {
+        let samples = self.channels.iter().map(Vec::len).max().unwrap_or(0);
+        samples as f64 / self.sample_rate
+    }
+
+    // A channel resampled to `sample_rate`, scaled so that the loudest channel has unit
+    // energy and white noise comes out at the level it went in
+    fn samples(&self, channel: usize, sample_rate: f64) -> Vec<f32> {
+        let resampled: Vec<Vec<f32>> = self
+            .channels
+            .iter()
+            .map(|samples| resample(samples, self.sample_rate, sample_rate))
+            .collect();
+        let energy = resampled
+            .iter()
+            .map(|samples| samples.iter().map(|x| x * x).sum::<f32>())
+            .fold(0.0, f32::max);
+        let gain = if energy > 0.0 {
+            energy.sqrt().recip()
+        } else {
+            1.0
+        };
+        let mut samples = resampled[channel].clone();
+        samples.iter_mut().for_each(|x| *x *= gain);
+        samples
+    }
+
+    // The kernel of a channel at `sample_rate`, transformed the first time it is asked for
+    fn kernel(&self, channel: usize, sample_rate: f64) -> Arc<Kernel> {
+        let channel = channel.checked_rem(self.channels.len()).unwrap_or(0);
+        let mut kernels = self
+            .kernels
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if let Some(kernel) = kernels
+            .iter()
+            .find(|kernel| kernel.channel == channel && kernel.sample_rate == sample_rate)
+        {
+            return kernel.clone();
+        }
+        let samples = if self.channels.is_empty() {
+            Vec::new()
+        } else {
+            self.samples(channel, sample_rate)
+        };
+        let kernel = Arc::new(Kernel::new(&samples, channel, sample_rate));
+        kernels.push(kernel.clone());
+        kernel
+    }
+}
+
+impl PartialEq for ImpulseResponse {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+            && self.sample_rate == other.sample_rate
+            && self.channels == other.channels
+    }
+}
+
+impl TryFrom<PathBuf> for ImpulseResponse {
+    type Error = String;
+
+    // Errors end up in a serde message, so the causes go on the same line
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if path.as_os_str().is_empty() {
+            return Err(
+                "no impulse response given; set `response` to the path of a WAV file".to_string(),
+            );
+        }
+        ImpulseResponse::load(&path).map_err(|error| format!("{error:#}"))
+    }
+}
+
+impl From<ImpulseResponse> for PathBuf {
+    fn from(response: ImpulseResponse) -> Self {
+        response.path
+    }
+}
+
+impl fmt::Debug for ImpulseResponse {
+    // The samples would drown out everything else
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImpulseResponse")
+            .field("path", &self.path)
+            .field("sample_rate", &self.sample_rate)
+            .field("channels", &self.channels.len())
+            .field("duration", &self.duration())
+            .finish()
+    }
+}
+
+// Linear interpolation is plenty for responses that are mostly smooth decays
+fn resample(samples: &[f32], from: f64, to: f64) -> Vec<f32> {
+    if from == to {
+        return samples.to_vec();
+    }
+    let step = from / to;
+    let length = (samples.len() as f64 / step).ceil() as usize;
+    (0..length)
+        .map(|i| {
+            let position = i as f64 * step;
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            let a = samples.get(index).copied().unwrap_or(0.0);
+            let b = samples.get(index + 1).copied().unwrap_or(0.0);
+            lerp(a, b, fraction)
+        })
+        .collect()
+}
+
+/// Mono convolution with one channel of an impulse response.
+///
+/// The response is cut into partitions that are convolved in the frequency domain, short
+/// ones at the start and longer ones further on, as in Gardner's non-uniform scheme. The
+/// partitions are transformed once per response, channel and sample rate and shared by
+/// every convolver using them, so each note of an instrument only brings its own input
+/// history. The output lags the input by [`Convolver::LATENCY`] samples.
+#[derive(Clone)]
+pub struct Convolver {
+    response: ImpulseResponse,
+    channel: usize,
+    kernel: Arc<Kernel>,
+    stages: Vec<Stage>,
+}
+
+impl Convolver {
+    /// Delay of the output in samples.
+    pub const LATENCY: usize = BLOCK;
+
+    /// Convolve with `channel` of the response, which wraps around for responses with
+    /// fewer channels, so a mono response serves every channel.
+    pub fn new(response: &ImpulseResponse, channel: usize) -> Self {
+        let kernel = response.kernel(channel, DEFAULT_SR);
+        Convolver {
+            response: response.clone(),
+            channel,
+            stages: kernel.partitions.iter().map(Stage::new).collect(),
+            kernel,
+        }
+    }
+}
+
+impl AudioNode for Convolver {
+    const ID: u64 = 0x636f_6e76;
+    type Inputs = U1;
+    type Outputs = U1;
+
+    fn reset(&mut self) {
+        self.stages.iter_mut().for_each(Stage::reset);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        if sample_rate != self.kernel.sample_rate {
+            self.kernel = self.response.kernel(self.channel, sample_rate);
+            self.stages = self.kernel.partitions.iter().map(Stage::new).collect();
+        }
+    }
+
+    #[inline]
+    fn tick(&mut self, input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let x = input[0];
+        [self
+            .stages
+            .iter_mut()
+            .zip(&self.kernel.partitions)
+            .map(|(stage, partitions)| stage.tick(partitions, x))
+            .sum::<f32>()]
+        .into()
+    }
+}
+
+// One channel of a response, partitioned and transformed for a sample rate
+struct Kernel {
+    channel: usize,
+    sample_rate: f64,
+    partitions: Vec<Partitions>,
+}
+
+impl Kernel {
+    fn new(samples: &[f32], channel: usize, sample_rate: f64) -> Self {
+        // Each stretch starts where the latency of its blocks, less that of the first
+        // ones, lines it up
+        let mut partitions = Vec::new();
+        let mut start = 0;
+        let mut block = BLOCK;
+        while start < samples.len() {
+            let end = if block >= MAX_BLOCK {
+                samples.len()
+            } else {
+                Ord::min(block * STAGE_GROWTH - BLOCK, samples.len())
+            };
+            partitions.push(Partitions::new(block, &samples[start..end]));
+            start = end;
+            block *= STAGE_GROWTH;
+        }
+        Kernel {
+            channel,
+            sample_rate,
+            partitions,
+        }
+    }
+}
+
+// A stretch of the response cut into blocks of the same length
+struct Partitions {
+    block: usize,
+    // Spectrum of every block, zero padded to twice its length, with block + 1 bins
+    spectra: Vec<Vec<Complex32>>,
+}
+
+impl Partitions {
+    fn new(block: usize, response: &[f32]) -> Self {
+        let mut padded = vec![0.0; block * 2];
+        let spectra = response
+            .chunks(block)
+            .map(|chunk| {
+                padded.fill(0.0);
+                padded[..chunk.len()].copy_from_slice(chunk);
+                let mut spectrum = vec![Complex32::default(); block + 1];
+                real_fft(&padded, &mut spectrum);
+                spectrum
+            })
+            .collect();
+        Partitions { block, spectra }
+    }
+}
+
+// Uniformly partitioned overlap-save convolution of one stretch of the response, with
+// a latency of one block
+#[derive(Clone)]
+struct Stage {
+    block: usize,
+    // Spectra of the latest input blocks, the newest at `newest`
+    history: Vec<Vec<Complex32>>,
+    newest: usize,
+    // The last two blocks of input, filling up the second one
+    input: Vec<f32>,
+    position: usize,
+    // The block being played back
+    output: Vec<f32>,
+    sum: Vec<Complex32>,
+    spectrum: Vec<Complex32>,
+    result: Vec<Complex32>,
+}
+
+impl Stage {
+    fn new(partitions: &Partitions) -> Self {
+        let block = partitions.block;
+        let size = block * 2;
+        Stage {
+            block,
+            history: vec![vec![Complex32::default(); block + 1]; partitions.spectra.len()],
+            newest: 0,
+            input: vec![0.0; size],
+            position: 0,
+            output: vec![0.0; block],
+            sum: vec![Complex32::default(); block + 1],
+            spectrum: vec![Complex32::default(); size],
+            result: vec![Complex32::default(); size],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.history
+            .iter_mut()
+            .for_each(|spectrum| spectrum.fill(Complex32::default()));
+        self.input.fill(0.0);
+        self.output.fill(0.0);
+        self.position = 0;
+    }
+
+    #[inline]
+    fn tick(&mut self, partitions: &Partitions, x: f32) -> f32 {
+        let y = self.output[self.position];
+        self.input[self.block + self.position] = x;
+        self.position += 1;
+        if self.position == self.block {
+            self.process(partitions);
+            self.position = 0;
+        }
+        y
+    }
+
+    fn process(&mut self, partitions: &Partitions) {
+        let count = self.history.len();
+        self.newest = (self.newest + 1) % count;
+        real_fft(&self.input, &mut self.history[self.newest]);
+
+        // Multiply each partition with the input from as many blocks ago
+        self.sum.fill(Complex32::default());
+        for (age, partition) in partitions.spectra.iter().enumerate() {
+            let spectrum = &self.history[(self.newest + count - age) % count];
+            for ((sum, a), b) in self.sum.iter_mut().zip(partition).zip(spectrum) {
+                *sum += a * b;
+            }
+        }
+
+        // The inverse transform is complex, so mirror the spectrum of the real signal
+        let size = self.block * 2;
+        self.spectrum[..=self.block].copy_from_slice(&self.sum);
+        for bin in 1..self.block {
+            self.spectrum[size - bin] = self.sum[bin].conj();
+        }
+        inverse_fft(&self.spectrum, &mut self.result);
+
+        // Only the second half is free of wraparound from the first block
+        for (y, bin) in self.output.iter_mut().zip(&self.result[self.block..]) {
+            *y = bin.re;
+        }
+        self.input.copy_within(self.block.., 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A decaying tone, long enough to reach the third stage of partitions
+    fn response(length: usize) -> Vec<f32> {
+        (0..length)
+            .map(|i| (i as f32 * 0.37).sin() * (-(i as f32) / 1500.0).exp())
+            .collect()
+    }
+
+    fn run(convolver: &mut Convolver, input: &[f32]) -> Vec<f32> {
+        input
+            .iter()
+            .map(|&x| convolver.tick(&[x].into())[0])
+            .collect()
+    }
+
+    #[test]
+    fn matches_direct_convolution() {
+        let samples = response(5000);
+        let energy = samples.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let response = ImpulseResponse::from_samples(DEFAULT_SR, vec![samples.clone()]).unwrap();
+        let mut convolver = Convolver::new(&response, 0);
+
+        let input: Vec<f32> = (0..8000).map(|i| rnd1(i) as f32 - 0.5).collect();
+        let output = run(&mut convolver, &input);
+        let mut error = 0.0f32;
+        for (t, &y) in output.iter().enumerate().skip(Convolver::LATENCY) {
+            let t = t - Convolver::LATENCY;
+            let expected = (0..=Ord::min(t, samples.len() - 1))
+                .map(|k| samples[k] * input[t - k])
+                .sum::<f32>()
+                / energy;
+            error = error.max((y - expected).abs());
+        }
+        assert!(error < 1.0e-5, "error {error}");
+    }
+
+    #[test]
+    fn impulses_come_out_after_the_latency() {
+        let response =
+            ImpulseResponse::from_samples(DEFAULT_SR, vec![vec![0.0, 0.0, 1.0], vec![1.0]])
+                .unwrap();
+        let mut input = vec![0.0; 200];
+        input[0] = 1.0;
+        for (channel, delay) in [(0, 2), (1, 0), (2, 2)] {
+            let output = run(&mut Convolver::new(&response, channel), &input);
+            let peak = output.iter().position(|&y| y > 0.5);
+            assert_eq!(peak, Some(Convolver::LATENCY + delay));
+        }
+    }
+
+    #[test]
+    fn kernels_are_shared() {
+        let response = ImpulseResponse::from_samples(44100.0, vec![response(1000)]).unwrap();
+        let mut first = Convolver::new(&response, 0);
+        let mut second = Convolver::new(&response.clone(), 0);
+        assert!(Arc::ptr_eq(&first.kernel, &second.kernel));
+        first.set_sample_rate(48000.0);
+        second.set_sample_rate(48000.0);
+        assert!(Arc::ptr_eq(&first.kernel, &second.kernel));
+        assert_eq!(response.kernels.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn resampled_responses_keep_unit_energy() {
+        let response = ImpulseResponse::from_samples(44100.0, vec![response(1000)]).unwrap();
+        let samples = response.samples(0, 96000.0);
+        assert_eq!(
+            samples.len(),
+            (1000.0 * 96000.0 / 44100.0f64).ceil() as usize
+        );
+        let energy = samples.iter().map(|x| x * x).sum::<f32>();
+        assert!((energy - 1.0).abs() < 1.0e-4);
+    }
+
+    #[test]
+    fn missing_responses() {
+        assert!(ImpulseResponse::from_samples(DEFAULT_SR, vec![]).is_err());
+        assert!(ImpulseResponse::from_samples(DEFAULT_SR, vec![Vec::new()]).is_err());
+        assert!(ImpulseResponse::try_from(PathBuf::new()).is_err());
+        let mut silent = Convolver::new(&ImpulseResponse::missing(), 1);
+        assert!(run(&mut silent, &[1.0; 100]).iter().all(|&y| y == 0.0));
+    }
+}
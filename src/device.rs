@@ -0,0 +1,138 @@
+//! Choosing cpal hosts, output devices and stream configs.
+
+use anyhow::{Context, anyhow, bail};
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{BufferSize, SampleRate, StreamConfig, SupportedBufferSize, SupportedStreamConfig};
+
+/// Print every available host with its output devices and their supported configs.
+pub fn list_devices() -> Result<(), anyhow::Error> {
+    for host_id in cpal::available_hosts() {
+        println!("Host: {}", host_id.name());
+        let host = match cpal::host_from_id(host_id) {
+            Ok(host) => host,
+            Err(err) => {
+                println!("  unavailable: {err}");
+                continue;
+            }
+        };
+
+        let default_name = host.default_output_device().and_then(|d| d.name().ok());
+        for device in host.output_devices()? {
+            let name = device.name().unwrap_or_else(|_| "<unknown>".to_string());
+            let marker = if Some(&name) == default_name.as_ref() {
+                " (default)"
+            } else {
+                ""
+            };
+            println!("  Device: {name}{marker}");
+
+            let configs = match device.supported_output_configs() {
+                Ok(configs) => configs,
+                Err(err) => {
+                    println!("    no configs: {err}");
+                    continue;
+                }
+            };
+            for config in configs {
+                let buffer = match config.buffer_size() {
+                    SupportedBufferSize::Range { min, max } => format!("{min}-{max} frames"),
+                    SupportedBufferSize::Unknown => "unknown".to_string(),
+                };
+                println!(
+                    "    {} ch, {}-{} Hz, {}, buffer {}",
+                    config.channels(),
+                    config.min_sample_rate().0,
+                    config.max_sample_rate().0,
+                    config.sample_format(),
+                    buffer
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Find a host by name, ignoring case, or fall back to the default host.
+pub fn select_host(name: Option<&str>) -> Result<cpal::Host, anyhow::Error> {
+    let Some(name) = name else {
+        return Ok(cpal::default_host());
+    };
+    let host_id = cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| anyhow!("No audio host named '{name}'; try --list-devices"))?;
+    Ok(cpal::host_from_id(host_id)?)
+}
+
+/// Find an output device by exact name first, then by a case-insensitive substring.
+pub fn select_device(host: &cpal::Host, name: Option<&str>) -> Result<cpal::Device, anyhow::Error> {
+    let Some(name) = name else {
+        return host
+            .default_output_device()
+            .context("Failed to find a default output device");
+    };
+
+    let mut devices: Vec<_> = host
+        .output_devices()?
+        .filter_map(|device| device.name().ok().map(|n| (n, device)))
+        .collect();
+    let needle = name.to_lowercase();
+    let index = devices
+        .iter()
+        .position(|(n, _)| n == name)
+        .or_else(|| {
+            devices
+                .iter()
+                .position(|(n, _)| n.to_lowercase().contains(&needle))
+        })
+        .ok_or_else(|| anyhow!("No output device matching '{name}'; try --list-devices"))?;
+    Ok(devices.swap_remove(index).1)
+}
+
+/// Pick a supported config. Without a sample rate this is the device default; otherwise the
+/// config closest to the default that supports the requested rate.
+pub fn select_config(
+    device: &cpal::Device,
+    sample_rate: Option<u32>,
+) -> Result<SupportedStreamConfig, anyhow::Error> {
+    let default = device.default_output_config()?;
+    let Some(sample_rate) = sample_rate else {
+        return Ok(default);
+    };
+    if default.sample_rate().0 == sample_rate {
+        return Ok(default);
+    }
+
+    let mut candidates: Vec<_> = device
+        .supported_output_configs()?
+        .filter_map(|config| config.try_with_sample_rate(SampleRate(sample_rate)))
+        .collect();
+    // Prefer the default channel count, then the default sample format
+    candidates.sort_by_key(|config| {
+        (
+            config.channels() != default.channels(),
+            config.sample_format() != default.sample_format(),
+        )
+    });
+    candidates
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("Device does not support a sample rate of {sample_rate} Hz"))
+}
+
+/// Turn a supported config into a stream config with an optional fixed buffer size.
+pub fn stream_config(
+    config: &SupportedStreamConfig,
+    buffer_size: Option<u32>,
+) -> Result<StreamConfig, anyhow::Error> {
+    let mut stream_config = config.config();
+    if let Some(frames) = buffer_size {
+        if let SupportedBufferSize::Range { min, max } = *config.buffer_size()
+            && !(min..=max).contains(&frames)
+        {
+            bail!("Buffer size {frames} is outside the supported range {min}-{max}");
+        }
+        stream_config.buffer_size = BufferSize::Fixed(frames);
+    }
+    Ok(stream_config)
+}
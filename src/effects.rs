@@ -0,0 +1,939 @@
+//! Master bus effects that process the mixed output of all notes.
+
+use crate::convolution::{Convolver, ImpulseResponse};
+use crate::tempo::Division;
+use anyhow::{Context, anyhow, bail};
+use fundsp::hacker::*;
+use serde::{Deserialize, Serialize};
+
+/// A single stereo effect stage.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Effect {
+    /// Stereo chorus made of two mono five-voice choruses.
+    Chorus {
+        separation: f32,
+        variation: f32,
+        mod_frequency: f32,
+    },
+    /// Flanger with the left and right sweeps a quarter period apart.
+    Flanger {
+        feedback: f32,
+        min_delay: f32,
+        max_delay: f32,
+        mod_frequency: f32,
+    },
+    /// Allpass phaser with the left and right sweeps a quarter period apart.
+    Phaser { feedback: f32, mod_frequency: f32 },
+    /// Stereo feedback delay. Echoes pass through the low and high cut filters on every
+    /// repeat; ping-pong echoes alternate between the left and right channels.
+    Delay {
+        time: DelayTime,
+        feedback: f32,
+        /// Wet/dry balance in 0...1.
+        mix: f32,
+        #[serde(default)]
+        ping_pong: bool,
+        /// Highpass cutoff of the echoes in Hz.
+        low_cut: f32,
+        /// Lowpass cutoff of the echoes in Hz.
+        high_cut: f32,
+    },
+    /// Algorithmic FDN reverb, fully wet.
+    Reverb {
+        room_size: f32,
+        time: f32,
+        damping: f32,
+    },
+    /// Convolution reverb with a room impulse response from a WAV file. A stereo response
+    /// gives each channel its own and a mono one is shared. The pre-delay in seconds
+    /// holds back the reverb, less the latency of the convolution.
+    Convolution {
+        response: ImpulseResponse,
+        pre_delay: f32,
+        /// Wet/dry balance in 0...1.
+        mix: f32,
+    },
+    /// Parametric EQ: a low shelf, a high shelf and any number of peaking bands in
+    /// between. Gains are in dB.
+    Eq {
+        low_frequency: f32,
+        low_gain: f32,
+        #[serde(default = "shelf_q")]
+        low_q: f32,
+        high_frequency: f32,
+        high_gain: f32,
+        #[serde(default = "shelf_q")]
+        high_q: f32,
+        #[serde(default)]
+        bands: Vec<EqBand>,
+    },
+    /// Feed-forward compressor. Threshold and makeup gain are in dB.
+    Compressor {
+        threshold: f32,
+        ratio: f32,
+        attack: f32,
+        release: f32,
+        makeup: f32,
+    },
+    /// Look-ahead limiter.
+    Limiter { attack: f32, release: f32 },
+}
+
+impl Effect {
+    /// The effect called `name` with its default parameters. There is no default room
+    /// for `convolution`, so its `response` must be set before it is built.
+    pub fn from_name(name: &str) -> Option<Effect> {
+        let effect = match name {
+            "chorus" => Effect::Chorus {
+                separation: 0.0,
+                variation: 0.002,
+                mod_frequency: 0.1,
+            },
+            "flanger" => Effect::Flanger {
+                feedback: 0.6,
+                min_delay: 0.005,
+                max_delay: 0.01,
+                mod_frequency: 0.1,
+            },
+            "phaser" => Effect::Phaser {
+                feedback: 0.5,
+                mod_frequency: 0.2,
+            },
+            "delay" => Effect::Delay {
+                time: DelayTime::Milliseconds(300.0),
+                feedback: 0.4,
+                mix: 0.3,
+                ping_pong: false,
+                low_cut: 100.0,
+                high_cut: 5000.0,
+            },
+            "pingpong" => Effect::Delay {
+                time: DelayTime::Division(Division::parse("1/8").expect("valid division")),
+                feedback: 0.5,
+                mix: 0.3,
+                ping_pong: true,
+                low_cut: 100.0,
+                high_cut: 5000.0,
+            },
+            "reverb" => Effect::Reverb {
+                room_size: 0.8,
+                time: 0.3,
+                damping: 0.02,
+            },
+            "convolution" => Effect::Convolution {
+                response: ImpulseResponse::missing(),
+                pre_delay: 0.02,
+                mix: 0.3,
+            },
+            "eq" => Effect::Eq {
+                low_frequency: 200.0,
+                low_gain: 0.0,
+                low_q: SHELF_Q,
+                high_frequency: 4000.0,
+                high_gain: 0.0,
+                high_q: SHELF_Q,
+                bands: Vec::new(),
+            },
+            "compressor" => Effect::Compressor {
+                threshold: -18.0,
+                ratio: 4.0,
+                attack: 0.01,
+                release: 0.2,
+                makeup: 0.0,
+            },
+            "limiter" => Effect::Limiter {
+                attack: 0.9,
+                release: 2.0,
+            },
+            _ => return None,
+        };
+        Some(effect)
+    }
+
+    /// Build the stereo unit for this stage; tempo-synced times follow `tempo` in BPM.
+    pub fn build(&self, tempo: f64) -> Box<dyn AudioUnit> {
+        match *self {
+            Effect::Chorus {
+                separation,
+                variation,
+                mod_frequency,
+            } => Box::new(
+                chorus(0, separation, variation, mod_frequency)
+                    | chorus(1, separation, variation, mod_frequency),
+            ),
+            Effect::Flanger {
+                feedback,
+                min_delay,
+                max_delay,
+                mod_frequency,
+            } => Box::new(
+                flanger(feedback, min_delay, max_delay, move |t| {
+                    lerp11(min_delay, max_delay, sin_hz(mod_frequency, t))
+                }) | flanger(feedback, min_delay, max_delay, move |t| {
+                    lerp11(min_delay, max_delay, cos_hz(mod_frequency, t))
+                }),
+            ),
+            Effect::Phaser {
+                feedback,
+                mod_frequency,
+            } => Box::new(
+                phaser(feedback, move |t| sin_hz(mod_frequency, t) * 0.5 + 0.5)
+                    | phaser(feedback, move |t| cos_hz(mod_frequency, t) * 0.5 + 0.5),
+            ),
+            Effect::Delay {
+                time,
+                feedback,
+                mix,
+                ping_pong,
+                low_cut,
+                high_cut,
+            } => Box::new(An(StereoDelay::new(
+                time.seconds(tempo),
+                feedback,
+                mix,
+                ping_pong,
+                low_cut,
+                high_cut,
+            ))),
+            Effect::Reverb {
+                room_size,
+                time,
+                damping,
+            } => Box::new(reverb_stereo(room_size, time, damping)),
+            Effect::Convolution {
+                ref response,
+                pre_delay,
+                mix,
+            } => Box::new(An(ConvolutionReverb::new(response, pre_delay, mix))),
+            Effect::Eq {
+                low_frequency,
+                low_gain,
+                low_q,
+                high_frequency,
+                high_gain,
+                high_q,
+                ref bands,
+            } => {
+                // The number of bands is only known at runtime, so each channel is a Net
+                let eq = || {
+                    let mut eq = Net::wrap(Box::new(
+                        lowshelf_hz(low_frequency, low_q, db_amp(low_gain))
+                            >> highshelf_hz(high_frequency, high_q, db_amp(high_gain)),
+                    ));
+                    for band in bands {
+                        eq = eq >> bell_hz(band.frequency, band.q, db_amp(band.gain));
+                    }
+                    eq
+                };
+                Box::new(eq() | eq())
+            }
+            Effect::Compressor {
+                threshold,
+                ratio,
+                attack,
+                release,
+                makeup,
+            } => Box::new(An(Compressor::new(
+                threshold, ratio, attack, release, makeup,
+            ))),
+            Effect::Limiter { attack, release } => Box::new(limiter_stereo(attack, release)),
+        }
+    }
+}
+
+// Butterworth Q, for shelves without a bump around the corner frequency
+const SHELF_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+fn shelf_q() -> f32 {
+    SHELF_Q
+}
+
+/// A peaking band of the parametric EQ. Gain is in dB.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EqBand {
+    pub frequency: f32,
+    pub gain: f32,
+    pub q: f32,
+}
+
+/// A delay time, either absolute or as a note division that follows the tempo.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, try_from = "RawDelayTime")]
+pub enum DelayTime {
+    Milliseconds(f64),
+    Division(Division),
+}
+
+// Read divisions as plain strings first, so a bad one reports why it is invalid
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawDelayTime {
+    Milliseconds(f64),
+    Division(String),
+}
+
+impl TryFrom<RawDelayTime> for DelayTime {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: RawDelayTime) -> Result<Self, Self::Error> {
+        match raw {
+            RawDelayTime::Milliseconds(ms) => Ok(DelayTime::Milliseconds(ms)),
+            RawDelayTime::Division(s) => Ok(DelayTime::Division(Division::parse(&s)?)),
+        }
+    }
+}
+
+impl DelayTime {
+    pub fn seconds(&self, tempo: f64) -> f64 {
+        match self {
+            DelayTime::Milliseconds(ms) => ms * 1.0e-3,
+            DelayTime::Division(division) => division.seconds(tempo),
+        }
+    }
+}
+
+/// An effect in a chain. Bypassed stages stay in the chain but pass the signal through.
+/// Parameters left out keep the defaults of [`Effect::from_name`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "toml::Table")]
+pub struct Stage {
+    #[serde(flatten)]
+    pub effect: Effect,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bypass: bool,
+}
+
+impl Stage {
+    pub fn new(effect: Effect) -> Self {
+        Stage {
+            effect,
+            bypass: false,
+        }
+    }
+
+    /// Parse a stage from `name[:key=value,...]`, e.g. `reverb:room_size=20,time=2`.
+    /// Missing parameters keep their defaults and a bare `bypass` key bypasses the stage.
+    pub fn parse(spec: &str) -> Result<Stage, anyhow::Error> {
+        let (name, parameters) = spec.split_once(':').unwrap_or((spec, ""));
+        let name = name.trim();
+        let mut table = toml::Table::new();
+        table.insert("type".to_string(), toml::Value::String(name.to_string()));
+        for parameter in split_parameters(parameters) {
+            let (key, value) = parameter.split_once('=').unwrap_or((parameter, "true"));
+            let key = key.trim();
+            if key == "type" {
+                bail!("Effect '{name}' has no parameter '{key}'");
+            }
+            // Anything that is not a TOML value, such as a note division, is a string
+            let value = parse_value(value.trim())
+                .unwrap_or_else(|| toml::Value::String(value.trim().to_string()));
+            table.insert(key.to_string(), value);
+        }
+        Stage::with_defaults(table)
+    }
+
+    // Build the stage named by `type` from its defaults with `parameters` laid over them
+    fn with_defaults(parameters: toml::Table) -> Result<Stage, anyhow::Error> {
+        let Some(toml::Value::String(name)) = parameters.get("type") else {
+            bail!("Effect stage has no `type`");
+        };
+        let name = name.clone();
+        let effect = Effect::from_name(&name).ok_or_else(|| anyhow!("Unknown effect '{name}'"))?;
+
+        // Go through the serialized form so parameters are checked by name and type
+        let mut table = toml::Table::try_from(Stage::new(effect))?;
+        table.insert("bypass".to_string(), toml::Value::Boolean(false));
+        // The defaults carry the serialized type, which is `delay` for `pingpong`
+        for (key, value) in parameters.into_iter().filter(|(key, _)| key != "type") {
+            let Some(default) = table.get(&key) else {
+                bail!("Effect '{name}' has no parameter '{key}'");
+            };
+            let value = with_floats(default, value);
+            table.insert(key, value);
+        }
+        let stage: FullStage = table
+            .try_into()
+            .with_context(|| format!("Invalid parameters for effect '{name}'"))?;
+        Ok(Stage {
+            effect: stage.effect,
+            bypass: stage.bypass,
+        })
+    }
+}
+
+impl TryFrom<toml::Table> for Stage {
+    type Error = String;
+
+    fn try_from(table: toml::Table) -> Result<Self, Self::Error> {
+        Stage::with_defaults(table).map_err(|error| format!("{error:#}"))
+    }
+}
+
+// A stage with every parameter given, as `Stage` reads once the defaults are filled in
+#[derive(Deserialize)]
+struct FullStage {
+    #[serde(flatten)]
+    effect: Effect,
+    #[serde(default)]
+    bypass: bool,
+}
+
+// Split `key=value` pairs on the commas that are not inside an array or inline table
+fn split_parameters(parameters: &str) -> impl Iterator<Item = &str> {
+    let mut depth = 0;
+    parameters
+        .split(move |c| {
+            match c {
+                '[' | '{' => depth += 1,
+                ']' | '}' => depth -= 1,
+                _ => {}
+            }
+            c == ',' && depth == 0
+        })
+        .filter(|p| !p.trim().is_empty())
+}
+
+// Whole numbers are fine where the default has floats, also inside arrays such as EQ bands
+fn with_floats(default: &toml::Value, value: toml::Value) -> toml::Value {
+    match (default, value) {
+        (toml::Value::Float(_), toml::Value::Integer(i)) => toml::Value::Float(i as f64),
+        (toml::Value::Array(_), toml::Value::Array(items)) => {
+            toml::Value::Array(items.into_iter().map(integers_to_floats).collect())
+        }
+        (_, value) => value,
+    }
+}
+
+fn integers_to_floats(value: toml::Value) -> toml::Value {
+    match value {
+        toml::Value::Integer(i) => toml::Value::Float(i as f64),
+        toml::Value::Array(items) => {
+            toml::Value::Array(items.into_iter().map(integers_to_floats).collect())
+        }
+        toml::Value::Table(table) => toml::Value::Table(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, integers_to_floats(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+// Parse a single TOML value such as `2.5` or `true`
+fn parse_value(text: &str) -> Option<toml::Value> {
+    let mut table: toml::Table = toml::from_str(&format!("value = {text}")).ok()?;
+    table.remove("value")
+}
+
+/// An ordered chain of effect stages.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct EffectsChain {
+    pub stages: Vec<Stage>,
+}
+
+impl Default for EffectsChain {
+    /// The original master bus: chorus, reverb and limiter.
+    fn default() -> Self {
+        EffectsChain::empty()
+            .chorus(0.0, 0.002, 0.1)
+            .reverb(0.8, 0.3, 0.02)
+            .limiter(0.9, 2.0)
+    }
+}
+
+impl EffectsChain {
+    /// The default chain with a 5 ms limiter look-ahead, since the default 0.9 s
+    /// look-ahead would delay every note played live by as much.
+    pub fn live() -> Self {
+        EffectsChain::empty()
+            .chorus(0.0, 0.002, 0.1)
+            .reverb(0.8, 0.3, 0.02)
+            .limiter(0.005, 0.5)
+    }
+
+    /// A chain that passes the signal through unchanged.
+    pub fn empty() -> Self {
+        EffectsChain { stages: Vec::new() }
+    }
+
+    /// Parse a chain from stage specs in order; see [`Stage::parse`].
+    pub fn parse<S: AsRef<str>>(specs: &[S]) -> Result<Self, anyhow::Error> {
+        let stages = specs
+            .iter()
+            .map(|spec| Stage::parse(spec.as_ref()))
+            .collect::<Result<_, _>>()?;
+        Ok(EffectsChain { stages })
+    }
+
+    /// Append a stage to the end of the chain.
+    pub fn with(mut self, effect: Effect) -> Self {
+        self.stages.push(Stage::new(effect));
+        self
+    }
+
+    pub fn chorus(self, separation: f32, variation: f32, mod_frequency: f32) -> Self {
+        self.with(Effect::Chorus {
+            separation,
+            variation,
+            mod_frequency,
+        })
+    }
+
+    pub fn flanger(
+        self,
+        feedback: f32,
+        min_delay: f32,
+        max_delay: f32,
+        mod_frequency: f32,
+    ) -> Self {
+        self.with(Effect::Flanger {
+            feedback,
+            min_delay,
+            max_delay,
+            mod_frequency,
+        })
+    }
+
+    pub fn phaser(self, feedback: f32, mod_frequency: f32) -> Self {
+        self.with(Effect::Phaser {
+            feedback,
+            mod_frequency,
+        })
+    }
+
+    /// Append a stereo delay with a 100 Hz to 5 kHz feedback band.
+    pub fn delay(self, time: DelayTime, feedback: f32, mix: f32) -> Self {
+        self.with(Effect::Delay {
+            time,
+            feedback,
+            mix,
+            ping_pong: false,
+            low_cut: 100.0,
+            high_cut: 5000.0,
+        })
+    }
+
+    /// Append a ping-pong delay with a 100 Hz to 5 kHz feedback band.
+    pub fn ping_pong(self, time: DelayTime, feedback: f32, mix: f32) -> Self {
+        self.with(Effect::Delay {
+            time,
+            feedback,
+            mix,
+            ping_pong: true,
+            low_cut: 100.0,
+            high_cut: 5000.0,
+        })
+    }
+
+    pub fn reverb(self, room_size: f32, time: f32, damping: f32) -> Self {
+        self.with(Effect::Reverb {
+            room_size,
+            time,
+            damping,
+        })
+    }
+
+    pub fn convolution(self, response: ImpulseResponse, pre_delay: f32, mix: f32) -> Self {
+        self.with(Effect::Convolution {
+            response,
+            pre_delay,
+            mix,
+        })
+    }
+
+    /// Append a parametric EQ with Butterworth shelves.
+    pub fn eq(
+        self,
+        low_frequency: f32,
+        low_gain: f32,
+        high_frequency: f32,
+        high_gain: f32,
+        bands: Vec<EqBand>,
+    ) -> Self {
+        self.with(Effect::Eq {
+            low_frequency,
+            low_gain,
+            low_q: SHELF_Q,
+            high_frequency,
+            high_gain,
+            high_q: SHELF_Q,
+            bands,
+        })
+    }
+
+    pub fn compressor(
+        self,
+        threshold: f32,
+        ratio: f32,
+        attack: f32,
+        release: f32,
+        makeup: f32,
+    ) -> Self {
+        self.with(Effect::Compressor {
+            threshold,
+            ratio,
+            attack,
+            release,
+            makeup,
+        })
+    }
+
+    pub fn limiter(self, attack: f32, release: f32) -> Self {
+        self.with(Effect::Limiter { attack, release })
+    }
+
+    /// Bypass the stage at `index`, if there is one.
+    pub fn bypass(mut self, index: usize) -> Self {
+        if let Some(stage) = self.stages.get_mut(index) {
+            stage.bypass = true;
+        }
+        self
+    }
+
+    /// Append the chain to `source` in `net` and return the id of the last node.
+    /// Bypassed stages are left out and tempo-synced stages follow `tempo` in BPM.
+    pub fn connect(&self, net: &mut Net, source: NodeId, tempo: f64) -> NodeId {
+        let mut previous = source;
+        for stage in self.stages.iter().filter(|stage| !stage.bypass) {
+            let id = net.push(stage.effect.build(tempo));
+            net.pipe_all(previous, id);
+            previous = id;
+        }
+        previous
+    }
+}
+
+/// Stereo feed-forward compressor with a hard knee. Both channels share one peak
+/// detector so the stereo image does not shift under gain reduction.
+#[derive(Clone)]
+struct Compressor {
+    threshold: f32,
+    slope: f32,
+    attack: f32,
+    release: f32,
+    makeup: f32,
+    attack_coefficient: f32,
+    release_coefficient: f32,
+    // Current gain reduction in dB
+    reduction: f32,
+}
+
+impl Compressor {
+    fn new(threshold: f32, ratio: f32, attack: f32, release: f32, makeup: f32) -> Self {
+        let mut node = Compressor {
+            threshold,
+            slope: 1.0 - 1.0 / ratio.max(1.0),
+            attack,
+            release,
+            makeup: db_amp(makeup),
+            attack_coefficient: 0.0,
+            release_coefficient: 0.0,
+            reduction: 0.0,
+        };
+        node.set_sample_rate(DEFAULT_SR);
+        node
+    }
+}
+
+impl AudioNode for Compressor {
+    const ID: u64 = 0x636f_6d70;
+    type Inputs = U2;
+    type Outputs = U2;
+
+    fn reset(&mut self) {
+        self.reduction = 0.0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        // One-pole smoothing that covers about 63% of a step in the given time
+        let coefficient = |time: f32| (-1.0 / (time.max(1.0e-4) as f64 * sample_rate)).exp() as f32;
+        self.attack_coefficient = coefficient(self.attack);
+        self.release_coefficient = coefficient(self.release);
+    }
+
+    fn tick(&mut self, input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let peak = input[0].abs().max(input[1].abs()).max(1.0e-6);
+        let target = ((amp_db(peak) - self.threshold) * self.slope).max(0.0);
+        let coefficient = if target > self.reduction {
+            self.attack_coefficient
+        } else {
+            self.release_coefficient
+        };
+        self.reduction = target + (self.reduction - target) * coefficient;
+        let gain = db_amp(-self.reduction) * self.makeup;
+        [input[0] * gain, input[1] * gain].into()
+    }
+}
+
+/// Stereo delay line with filtered feedback, band-limiting the echoes on every repeat.
+/// The buffers hold exactly the delay time at the current sample rate.
+#[derive(Clone)]
+struct StereoDelay {
+    time: f64,
+    feedback: f32,
+    mix: f32,
+    ping_pong: bool,
+    low_cut: f32,
+    high_cut: f32,
+    buffers: [Vec<f32>; 2],
+    position: usize,
+    highpass_coefficient: f32,
+    lowpass_coefficient: f32,
+    // One-pole filter states per channel
+    highpass_state: [f32; 2],
+    lowpass_state: [f32; 2],
+}
+
+impl StereoDelay {
+    fn new(
+        time: f64,
+        feedback: f32,
+        mix: f32,
+        ping_pong: bool,
+        low_cut: f32,
+        high_cut: f32,
+    ) -> Self {
+        let mut node = StereoDelay {
+            time: time.max(0.0),
+            feedback: feedback.clamp(0.0, 0.99),
+            mix: mix.clamp(0.0, 1.0),
+            ping_pong,
+            low_cut,
+            high_cut,
+            buffers: [Vec::new(), Vec::new()],
+            position: 0,
+            highpass_coefficient: 0.0,
+            lowpass_coefficient: 0.0,
+            highpass_state: [0.0; 2],
+            lowpass_state: [0.0; 2],
+        };
+        node.set_sample_rate(DEFAULT_SR);
+        node
+    }
+
+    // Band-limit an echo on its way out of the delay line
+    fn filter(&mut self, channel: usize, x: f32) -> f32 {
+        self.lowpass_state[channel] += (x - self.lowpass_state[channel]) * self.lowpass_coefficient;
+        self.highpass_state[channel] += (self.lowpass_state[channel]
+            - self.highpass_state[channel])
+            * self.highpass_coefficient;
+        self.lowpass_state[channel] - self.highpass_state[channel]
+    }
+}
+
+impl AudioNode for StereoDelay {
+    const ID: u64 = 0x6465_6c61;
+    type Inputs = U2;
+    type Outputs = U2;
+
+    fn reset(&mut self) {
+        for buffer in &mut self.buffers {
+            buffer.fill(0.0);
+        }
+        self.position = 0;
+        self.highpass_state = [0.0; 2];
+        self.lowpass_state = [0.0; 2];
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        let length = Ord::max((self.time * sample_rate).round() as usize, 1);
+        self.buffers = [vec![0.0; length], vec![0.0; length]];
+        self.position = 0;
+        let coefficient = |cutoff: f32| {
+            (1.0 - (-std::f64::consts::TAU * cutoff as f64 / sample_rate).exp()) as f32
+        };
+        self.highpass_coefficient = coefficient(self.low_cut);
+        self.lowpass_coefficient = coefficient(self.high_cut);
+        self.highpass_state = [0.0; 2];
+        self.lowpass_state = [0.0; 2];
+    }
+
+    fn tick(&mut self, input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let position = self.position;
+        let left = self.filter(0, self.buffers[0][position]);
+        let right = self.filter(1, self.buffers[1][position]);
+
+        if self.ping_pong {
+            // The mono input enters on the left and each repeat crosses over
+            self.buffers[0][position] = (input[0] + input[1]) * 0.5 + right * self.feedback;
+            self.buffers[1][position] = left * self.feedback;
+        } else {
+            self.buffers[0][position] = input[0] + left * self.feedback;
+            self.buffers[1][position] = input[1] + right * self.feedback;
+        }
+        self.position = (position + 1) % self.buffers[0].len();
+
+        let dry = 1.0 - self.mix;
+        [
+            input[0] * dry + left * self.mix,
+            input[1] * dry + right * self.mix,
+        ]
+        .into()
+    }
+}
+
+/// Stereo convolution reverb behind a pre-delay. The convolvers lag by
+/// [`Convolver::LATENCY`] samples, which the pre-delay makes up for where it is long
+/// enough, and the dry signal is not delayed at all.
+#[derive(Clone)]
+struct ConvolutionReverb {
+    pre_delay: f32,
+    mix: f32,
+    convolvers: [Convolver; 2],
+    buffers: [Vec<f32>; 2],
+    position: usize,
+}
+
+impl ConvolutionReverb {
+    fn new(response: &ImpulseResponse, pre_delay: f32, mix: f32) -> Self {
+        let mut node = ConvolutionReverb {
+            pre_delay: pre_delay.max(0.0),
+            mix: mix.clamp(0.0, 1.0),
+            convolvers: [Convolver::new(response, 0), Convolver::new(response, 1)],
+            buffers: [Vec::new(), Vec::new()],
+            position: 0,
+        };
+        node.set_sample_rate(DEFAULT_SR);
+        node
+    }
+}
+
+impl AudioNode for ConvolutionReverb {
+    const ID: u64 = 0x6972_7276;
+    type Inputs = U2;
+    type Outputs = U2;
+
+    fn reset(&mut self) {
+        for convolver in &mut self.convolvers {
+            convolver.reset();
+        }
+        for buffer in &mut self.buffers {
+            buffer.fill(0.0);
+        }
+        self.position = 0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        for convolver in &mut self.convolvers {
+            convolver.set_sample_rate(sample_rate);
+        }
+        let length = (self.pre_delay as f64 * sample_rate).round() as usize;
+        let length = length.saturating_sub(Convolver::LATENCY);
+        self.buffers = [vec![0.0; length], vec![0.0; length]];
+        self.position = 0;
+    }
+
+    fn tick(&mut self, input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let mut delayed = [input[0], input[1]];
+        if !self.buffers[0].is_empty() {
+            for (channel, x) in delayed.iter_mut().enumerate() {
+                *x = std::mem::replace(&mut self.buffers[channel][self.position], *x);
+            }
+            self.position = (self.position + 1) % self.buffers[0].len();
+        }
+        let left = self.convolvers[0].tick(&[delayed[0]].into())[0];
+        let right = self.convolvers[1].tick(&[delayed[1]].into())[0];
+
+        let dry = 1.0 - self.mix;
+        [
+            input[0] * dry + left * self.mix,
+            input[1] * dry + right * self.mix,
+        ]
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameters_override_the_defaults() {
+        let stage = Stage::parse("reverb:room_size=20,time=2").unwrap();
+        assert_eq!(
+            stage,
+            Stage::new(Effect::Reverb {
+                room_size: 20.0,
+                time: 2.0,
+                damping: 0.02,
+            })
+        );
+        assert!(Stage::parse("delay:bypass").unwrap().bypass);
+        assert_eq!(
+            Stage::parse("delay:time=1/8d").unwrap().effect,
+            Effect::Delay {
+                time: DelayTime::Division(Division::parse("1/8d").unwrap()),
+                feedback: 0.4,
+                mix: 0.3,
+                ping_pong: false,
+                low_cut: 100.0,
+                high_cut: 5000.0,
+            }
+        );
+    }
+
+    #[test]
+    fn eq_bands() {
+        let stage = Stage::parse("eq:low_gain=3,bands=[{frequency=800,gain=-4,q=2}]").unwrap();
+        let Effect::Eq {
+            low_gain, bands, ..
+        } = stage.effect
+        else {
+            panic!("expected an EQ, got {stage:?}");
+        };
+        assert_eq!(low_gain, 3.0);
+        assert_eq!(
+            bands,
+            [EqBand {
+                frequency: 800.0,
+                gain: -4.0,
+                q: 2.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn invalid_stages() {
+        assert!(Stage::parse("wah").is_err());
+        assert!(Stage::parse("reverb:size=20").is_err());
+        assert!(Stage::parse("reverb:type=delay").is_err());
+        assert!(Stage::parse("delay:time=1/0").is_err());
+        assert!(Stage::parse("limiter:attack=fast").is_err());
+    }
+
+    #[test]
+    fn chains_round_trip_through_toml() {
+        let specs = [
+            "chorus",
+            "flanger:feedback=0.3",
+            "phaser:bypass",
+            "pingpong:time=1/4t,mix=0.5",
+            "delay:time=250",
+            "reverb",
+            "eq:high_gain=-2,bands=[{frequency=300,gain=2,q=1.5}]",
+            "compressor:ratio=8",
+            "limiter",
+        ];
+        let chain = EffectsChain::parse(&specs).unwrap();
+        assert_eq!(chain.stages.len(), specs.len());
+
+        #[derive(Serialize, Deserialize)]
+        struct Document {
+            effects: EffectsChain,
+        }
+        let text = toml::to_string(&Document {
+            effects: chain.clone(),
+        })
+        .unwrap();
+        let parsed: Document = toml::from_str(&text).unwrap();
+        assert_eq!(parsed.effects, chain);
+        assert_eq!(
+            EffectsChain::parse::<&str>(&[]).unwrap(),
+            EffectsChain::empty()
+        );
+    }
+}
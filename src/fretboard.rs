@@ -0,0 +1,119 @@
+//! Six-string guitar chords: open-string tunings and fretted chord shapes.
+
+use crate::score::parse_pitch;
+use anyhow::{Context, bail};
+
+/// Number of strings on the guitar.
+pub const STRINGS: usize = 6;
+
+/// MIDI notes of the open strings, from the lowest string to the highest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fretboard {
+    pub strings: [u8; STRINGS],
+}
+
+impl Default for Fretboard {
+    fn default() -> Self {
+        Fretboard::STANDARD
+    }
+}
+
+impl Fretboard {
+    /// Standard tuning, E2 A2 D3 G3 B3 E4.
+    pub const STANDARD: Fretboard = Fretboard {
+        strings: [40, 45, 50, 55, 59, 64],
+    };
+
+    /// Parse six open-string pitches from low to high, e.g. `D2 A2 D3 G3 B3 E4` for drop D.
+    /// Open strings are whole MIDI notes; retune microtonally with a tuning instead.
+    pub fn parse(s: &str) -> Result<Fretboard, anyhow::Error> {
+        let pitches: Vec<&str> = s.split_whitespace().collect();
+        let Ok(pitches) = <[&str; STRINGS]>::try_from(pitches) else {
+            bail!("expected {STRINGS} open-string pitches in '{s}'");
+        };
+        let mut strings = [0; STRINGS];
+        for (string, pitch) in strings.iter_mut().zip(pitches) {
+            let note = parse_pitch(pitch)?;
+            if note.fract() != 0.0 || !(0.0..=127.0).contains(&note) {
+                bail!("open string '{pitch}' is not a whole MIDI note in 0...127");
+            }
+            *string = note as u8;
+        }
+        Ok(Fretboard { strings })
+    }
+
+    /// The string and MIDI note of every string a shape plays, from low to high.
+    pub fn pitches(&self, shape: &Shape) -> Vec<(usize, u8)> {
+        self.strings
+            .iter()
+            .zip(shape.frets)
+            .enumerate()
+            .filter_map(|(string, (&open, fret))| Some((string, open.saturating_add(fret?))))
+            .filter(|&(_, note)| note <= 127)
+            .collect()
+    }
+}
+
+/// A chord shape: the fret held down on each string from low to high, with `None` for a
+/// muted string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shape {
+    pub frets: [Option<u8>; STRINGS],
+}
+
+impl Shape {
+    /// Parse a shape written one character per string, such as `x32010` for C major,
+    /// or with frets separated by `-` when some are 10 or higher, as in `8-10-10-9-8-8`.
+    /// `x` marks a muted string.
+    pub fn parse(s: &str) -> Result<Shape, anyhow::Error> {
+        let fields: Vec<&str> = if s.contains('-') {
+            s.split('-').collect()
+        } else {
+            s.split("").filter(|c| !c.is_empty()).collect()
+        };
+        let Ok(fields) = <[&str; STRINGS]>::try_from(fields) else {
+            bail!("invalid chord shape '{s}'; expected {STRINGS} frets such as x32010");
+        };
+        let mut frets = [None; STRINGS];
+        for (fret, field) in frets.iter_mut().zip(fields) {
+            if !field.eq_ignore_ascii_case("x") {
+                let number = field
+                    .parse::<u8>()
+                    .with_context(|| format!("invalid fret '{field}' in chord shape '{s}'"))?;
+                *fret = Some(number);
+            }
+        }
+        Ok(Shape { frets })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes() {
+        let c = Shape::parse("x32010").unwrap();
+        assert_eq!(c.frets, [None, Some(3), Some(2), Some(0), Some(1), Some(0)]);
+        let barre = Shape::parse("8-10-10-9-8-8").unwrap();
+        assert_eq!(barre.frets.map(Option::unwrap), [8, 10, 10, 9, 8, 8]);
+        assert!(Shape::parse("x3201").is_err());
+        assert!(Shape::parse("x3201y").is_err());
+    }
+
+    #[test]
+    fn shapes_on_the_fretboard() {
+        let c = Shape::parse("x32010").unwrap();
+        assert_eq!(
+            Fretboard::STANDARD.pitches(&c),
+            [(1, 48), (2, 52), (3, 55), (4, 60), (5, 64)]
+        );
+        let drop_d = Fretboard::parse("D2 A2 D3 G3 B3 E4").unwrap();
+        assert_eq!(drop_d.strings, [38, 45, 50, 55, 59, 64]);
+        let power = Shape::parse("000xxx").unwrap();
+        assert_eq!(drop_d.pitches(&power), [(0, 38), (1, 45), (2, 50)]);
+        assert!(Fretboard::parse("E2 A2 D3").is_err());
+        assert!(Fretboard::parse("E2-14c A2 D3 G3 B3 E4").is_err());
+        assert!(Fretboard::parse("C-1-100c A2 D3 G3 B3 E4").is_err());
+    }
+}
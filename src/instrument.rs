@@ -0,0 +1,466 @@
+//! Instruments that turn a note into a fundsp graph.
+
+use crate::articulation::{Articulation, Technique, pitch_offset};
+use crate::convolution::{Convolver, ImpulseResponse};
+use clap::ValueEnum;
+use fundsp::hacker::*;
+use serde::{Deserialize, Serialize};
+
+/// Everything an instrument needs to know to build a single note.
+#[derive(Clone, Debug)]
+pub struct NoteParams {
+    pub frequency: f32,
+    /// Velocity in 0...1.
+    pub velocity: f32,
+    /// Time from note-on to note-off in seconds; infinite for live notes until they are released.
+    pub duration: f64,
+    /// Pitch changes while the note rings, followed by the plucked string voices.
+    pub articulations: Vec<Articulation>,
+    /// How the plucked string voices play the note.
+    pub technique: Technique,
+    /// Where the string is plucked, as a fraction of its length from the bridge. Plucking
+    /// at 1/n of the length cancels every nth partial. `None` leaves the tone unfiltered.
+    pub pick_position: Option<f32>,
+}
+
+/// A voice that builds a stereo fundsp graph for each note it plays.
+/// Instruments are `Send` so live playback can build notes on the MIDI input thread.
+pub trait Instrument: Send {
+    fn note(&self, note: &NoteParams) -> Box<dyn AudioUnit>;
+
+    /// Extra time the note keeps sounding after its duration.
+    fn release(&self) -> f64;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum InstrumentKind {
+    #[default]
+    Guitar,
+    Karplus,
+    Organ,
+}
+
+impl InstrumentKind {
+    pub fn build(self) -> Box<dyn Instrument> {
+        match self {
+            InstrumentKind::Guitar => Box::new(Guitar::default()),
+            InstrumentKind::Karplus => Box::new(Karplus),
+            InstrumentKind::Organ => Box::new(Organ),
+        }
+    }
+}
+
+// Noise burst that excites a plucked string. Harder plucks get a louder and longer burst,
+// and hammer-ons and pull-offs excite the string again more softly.
+fn pluck_excitation(
+    velocity: f32,
+    articulations: &[Articulation],
+) -> An<impl AudioNode<Inputs = U0, Outputs = U1> + use<>> {
+    let amplitude = velocity as f64;
+    let excitation_time = lerp(0.004, 0.01, velocity as f64);
+    let mut bursts = vec![(0.0, amplitude)];
+    for articulation in articulations {
+        match *articulation {
+            Articulation::HammerOn { time, .. } => bursts.push((time, amplitude * 0.3)),
+            Articulation::PullOff { time, .. } => bursts.push((time, amplitude * 0.5)),
+            _ => {}
+        }
+    }
+    white()
+        * envelope(move |t| {
+            bursts
+                .iter()
+                .filter(|&&(start, _)| (start..start + excitation_time).contains(&t))
+                .map(|&(_, amplitude)| amplitude)
+                .sum::<f64>()
+        })
+}
+
+// A plucked string whose pitch follows the articulations of the note and whose tone
+// follows its technique and pick position
+fn plucked_string(note: &NoteParams, gain_per_second: f32, high_frequency_damping: f32) -> Net {
+    let velocity = note.velocity.clamp(0.0, 1.0);
+    let (multiple, gain_per_second, high_frequency_damping, level) = match note.technique {
+        Technique::Open => (1, gain_per_second, high_frequency_damping, 1.0),
+        Technique::PalmMute => (
+            1,
+            PALM_MUTE_GAIN_PER_SECOND,
+            lerp(high_frequency_damping, 1.0, 0.6),
+            0.8,
+        ),
+        Technique::NaturalHarmonic { multiple } => (
+            multiple,
+            gain_per_second,
+            lerp(high_frequency_damping, 1.0, 0.5),
+            0.6,
+        ),
+        Technique::ArtificialHarmonic { multiple } => {
+            (multiple, gain_per_second, high_frequency_damping, 0.5)
+        }
+    };
+    let frequency = note.frequency as f64 * Ord::max(multiple, 1) as f64;
+    let articulations = note.articulations.clone();
+    let pitch = envelope(move |t| frequency * (pitch_offset(&articulations, t) / 12.0).exp2());
+
+    // Plucking at a point cancels the partials with a node there: a comb filter with the
+    // time the wave takes to travel to the bridge and back to that point
+    let pick_position = note
+        .pick_position
+        .map(|position| position.clamp(MIN_PICK_POSITION, 1.0 - MIN_PICK_POSITION));
+    let mut excitation = Net::wrap(Box::new(pluck_excitation(velocity, &note.articulations)));
+    if let Some(position) = pick_position {
+        let period = 1.0 / note.frequency;
+        excitation = excitation >> (pass() & delay(position * period) * -1.0);
+    }
+
+    (excitation | Net::wrap(Box::new(pitch)))
+        >> An(GuitarString::new(
+            velocity,
+            gain_per_second,
+            high_frequency_damping,
+            pick_position,
+        )) * level
+}
+
+/// A value that follows note velocity, from the softest to the hardest pluck.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VelocityRange {
+    pub soft: f32,
+    pub hard: f32,
+}
+
+impl VelocityRange {
+    pub fn at(&self, velocity: f32) -> f32 {
+        lerp(self.soft, self.hard, velocity)
+    }
+}
+
+// Either end of a range may be left out of a preset and keeps the built-in default
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PartialRange {
+    soft: Option<f32>,
+    hard: Option<f32>,
+}
+
+impl PartialRange {
+    fn or(self, default: VelocityRange) -> VelocityRange {
+        VelocityRange {
+            soft: self.soft.unwrap_or(default.soft),
+            hard: self.hard.unwrap_or(default.hard),
+        }
+    }
+}
+
+fn hf_damping_or_default<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<VelocityRange, D::Error> {
+    Ok(PartialRange::deserialize(deserializer)?.or(Guitar::default().hf_damping))
+}
+
+fn cutoff_or_default<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<VelocityRange, D::Error> {
+    Ok(PartialRange::deserialize(deserializer)?.or(Guitar::default().cutoff))
+}
+
+/// A bandpass resonance of the guitar body, mixed in parallel with the string.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Resonance {
+    pub frequency: f32,
+    pub q: f32,
+    pub gain: f32,
+}
+
+/// Acoustic guitar: a plucked string through body resonances, or through a recorded
+/// impulse response of a body. Velocity also lowers the string damping and raises the
+/// lowpass cutoff, so harder plucks sound brighter.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Guitar {
+    /// Body resonances added to the direct string sound.
+    pub body: Vec<Resonance>,
+    /// Impulse response of a guitar body, convolved with the string in place of the
+    /// resonances. Its first channel is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_response: Option<ImpulseResponse>,
+    /// Gain per second of the string loop.
+    pub damping: f32,
+    /// High frequency damping of the string loop in 0...1.
+    #[serde(deserialize_with = "hf_damping_or_default")]
+    pub hf_damping: VelocityRange,
+    /// Cutoff of the output lowpass in Hz.
+    #[serde(deserialize_with = "cutoff_or_default")]
+    pub cutoff: VelocityRange,
+    pub gain: f32,
+    /// Time in seconds the string keeps ringing after note-off.
+    pub release: f64,
+}
+
+impl Default for Guitar {
+    fn default() -> Self {
+        Guitar {
+            body: vec![
+                // Low body resonance
+                Resonance {
+                    frequency: 110.0,
+                    q: 1.5,
+                    gain: 0.15,
+                },
+                // Primary body resonance
+                Resonance {
+                    frequency: 200.0,
+                    q: 2.0,
+                    gain: 0.25,
+                },
+                // Mid-body resonance
+                Resonance {
+                    frequency: 400.0,
+                    q: 2.5,
+                    gain: 0.2,
+                },
+                // High frequency brightness
+                Resonance {
+                    frequency: 800.0,
+                    q: 3.0,
+                    gain: 0.1,
+                },
+            ],
+            body_response: None,
+            damping: 0.996,
+            hf_damping: VelocityRange {
+                soft: 0.6,
+                hard: 0.3,
+            },
+            cutoff: VelocityRange {
+                soft: 1500.0,
+                hard: 6000.0,
+            },
+            gain: 0.7,
+            release: 1.0,
+        }
+    }
+}
+
+impl Instrument for Guitar {
+    fn note(&self, note: &NoteParams) -> Box<dyn AudioUnit> {
+        let velocity = note.velocity.clamp(0.0, 1.0);
+        let high_frequency_damping = self.hf_damping.at(velocity);
+        let mut cutoff = self.cutoff.at(velocity);
+        if note.technique == Technique::PalmMute {
+            cutoff *= PALM_MUTE_CUTOFF;
+        }
+
+        // The body is only known at runtime, so it is summed up in a Net
+        let body = match &self.body_response {
+            Some(response) => Net::wrap(Box::new(An(Convolver::new(response, 0)))),
+            None => {
+                let mut body = Net::wrap(Box::new(pass()));
+                for resonance in &self.body {
+                    body = body & bandpass_hz(resonance.frequency, resonance.q) * resonance.gain;
+                }
+                body
+            }
+        };
+
+        Box::new(
+            plucked_string(note, self.damping, high_frequency_damping)
+                >> body
+                >> lowpass_hz(cutoff, 1.0)
+                >> dcblock() * self.gain
+                >> pan(0.0),
+        )
+    }
+
+    fn release(&self) -> f64 {
+        self.release
+    }
+}
+
+/// Plain Karplus-Strong string without any body or tone shaping.
+pub struct Karplus;
+
+impl Instrument for Karplus {
+    fn note(&self, note: &NoteParams) -> Box<dyn AudioUnit> {
+        Box::new(plucked_string(note, 0.996, 0.5) >> dcblock() * 0.7 >> pan(0.0))
+    }
+
+    fn release(&self) -> f64 {
+        1.0
+    }
+}
+
+/// Additive drawbar organ that sustains for the whole note.
+pub struct Organ;
+
+impl Instrument for Organ {
+    fn note(&self, note: &NoteParams) -> Box<dyn AudioUnit> {
+        let f = note.frequency;
+        let gain = 0.15 * note.velocity.clamp(0.0, 1.0) as f64;
+        let duration = note.duration;
+        let release = self.release();
+
+        Box::new(
+            (sine_hz(f * 0.5) * 0.6       // 16' sub-octave
+                + sine_hz(f) * 1.0        // 8' fundamental
+                + sine_hz(f * 1.5) * 0.4  // 5 1/3' quint
+                + sine_hz(f * 2.0) * 0.5  // 4' octave
+                + sine_hz(f * 3.0) * 0.25 // 2 2/3' twelfth
+                + sine_hz(f * 4.0) * 0.2) // 2' fifteenth
+                * envelope(move |t| {
+                    // Hold until note-off, then release linearly
+                    gain * clamp01(1.0 - (t - duration).max(0.0) / release)
+                })
+                >> pan(0.0),
+        )
+    }
+
+    fn release(&self) -> f64 {
+        0.1
+    }
+}
+
+// Lowest pitch a string can move to, which sizes its delay line
+const LOWEST_STRING_FREQUENCY: f64 = 20.0;
+
+// Plucking right at either end of the string would cancel it altogether
+const MIN_PICK_POSITION: f32 = 0.02;
+
+// A palm-muted string loses most of its level within a fraction of a second
+const PALM_MUTE_GAIN_PER_SECOND: f32 = 0.002;
+
+// Palm muting also darkens the guitar output filter by this factor
+const PALM_MUTE_CUTOFF: f32 = 0.5;
+
+/// Karplus-Strong string like fundsp's `pluck`, with the same loop damping, but tuned by
+/// a second input in Hz so its pitch can move while it rings. The loop delay is read
+/// with cubic interpolation from a line long enough for the lowest pitch.
+#[derive(Clone)]
+struct GuitarString {
+    // Peak level of the initial noise, which follows the velocity of the pluck
+    amplitude: f32,
+    gain_per_second: f64,
+    // Fraction of the string length from the bridge that shapes the initial noise
+    pick_position: Option<f32>,
+    // Taps of the symmetric three-tap loop filter, which delays by one sample
+    damping: (f32, f32),
+    damping_state: [f32; 2],
+    line: Vec<f32>,
+    position: usize,
+    sample_rate: f64,
+    hash: u64,
+    initialized: bool,
+}
+
+impl GuitarString {
+    fn new(
+        amplitude: f32,
+        gain_per_second: f32,
+        high_frequency_damping: f32,
+        pick_position: Option<f32>,
+    ) -> Self {
+        // Same taps as fir3 with the gain at Nyquist given by the damping
+        let alpha = (2.0 - high_frequency_damping) / 2.0;
+        let beta = (1.0 - alpha) / 2.0;
+        let mut string = GuitarString {
+            amplitude,
+            gain_per_second: gain_per_second as f64,
+            pick_position,
+            damping: (beta, alpha),
+            damping_state: [0.0; 2],
+            line: Vec::new(),
+            position: 0,
+            sample_rate: 0.0,
+            hash: 0,
+            initialized: false,
+        };
+        string.set_sample_rate(DEFAULT_SR);
+        string
+    }
+
+    // Fill one period behind the write position with zero-mean noise, as `pluck` does,
+    // comb filtered around the period for the pick position
+    fn initialize(&mut self, period: usize) {
+        let period = Ord::max(period, 1);
+        let mut noise: Vec<f32> = (0..period)
+            .map(|i| (rnd1(self.hash.wrapping_add(i as u64)) as f32 * 2.0 - 1.0) * self.amplitude)
+            .collect();
+        if let Some(position) = self.pick_position {
+            let offset = (position as f64 * period as f64).round() as usize % period;
+            noise = (0..period)
+                .map(|i| noise[i] - noise[(i + period - offset) % period])
+                .collect();
+        }
+        let mean = noise.iter().sum::<f32>() / period as f32;
+        let length = self.line.len();
+        for (i, sample) in noise.into_iter().enumerate() {
+            self.line[(self.position + length - period + i) % length] = sample - mean;
+        }
+        self.initialized = true;
+    }
+
+    // The line `delay` samples back from the write position, with cubic interpolation
+    fn read(&self, delay: f64) -> f32 {
+        let length = self.line.len();
+        let whole = delay.floor() as usize;
+        let fraction = (delay - delay.floor()) as f32;
+        let back = |samples: usize| self.line[(self.position + length - samples) % length];
+        let (y0, y1, y2, y3) = (
+            back(whole - 1),
+            back(whole),
+            back(whole + 1),
+            back(whole + 2),
+        );
+        let c1 = 0.5 * (y2 - y0);
+        let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+        let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+        ((c3 * fraction + c2) * fraction + c1) * fraction + y1
+    }
+}
+
+impl AudioNode for GuitarString {
+    const ID: u64 = 0x7374_726e;
+    type Inputs = U2;
+    type Outputs = U1;
+
+    fn reset(&mut self) {
+        self.line.fill(0.0);
+        self.damping_state = [0.0; 2];
+        self.position = 0;
+        self.initialized = false;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        if self.sample_rate != sample_rate {
+            self.sample_rate = sample_rate;
+            let length = (sample_rate / LOWEST_STRING_FREQUENCY).ceil() as usize + 4;
+            self.line = vec![0.0; length];
+            self.reset();
+        }
+    }
+
+    fn set_hash(&mut self, hash: u64) {
+        self.hash = hash;
+    }
+
+    fn tick(&mut self, input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let frequency = (input[1] as f64).clamp(LOWEST_STRING_FREQUENCY, self.sample_rate / 4.0);
+        // The loop filter accounts for one sample of the period
+        let delay = self.sample_rate / frequency - 1.0;
+        if !self.initialized {
+            self.initialize(delay.round() as usize);
+        }
+
+        let gain = self.gain_per_second.powf(1.0 / frequency) as f32;
+        let x = self.read(delay) * gain + input[0];
+        let (beta, alpha) = self.damping;
+        let output = beta * x + alpha * self.damping_state[0] + beta * self.damping_state[1];
+        self.damping_state = [x, self.damping_state[0]];
+
+        self.line[self.position] = output;
+        self.position = (self.position + 1) % self.line.len();
+        [output].into()
+    }
+}
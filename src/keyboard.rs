@@ -0,0 +1,190 @@
+//! Playing an instrument from the computer keyboard in a terminal.
+
+use crate::live::Voices;
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::terminal::{self, ClearType};
+use crossterm::{execute, queue};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Keys of the two piano-like rows, from C upwards: the home row plays the white keys
+/// and the row above it the black keys.
+const KEYS: [char; 17] = [
+    'a', 'w', 's', 'e', 'd', 'f', 't', 'g', 'y', 'h', 'u', 'j', 'k', 'o', 'l', 'p', ';',
+];
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+// Octave of the `a` key at startup, so it plays C4
+const DEFAULT_OCTAVE: i32 = 4;
+
+// Keep every key on the keyboard inside the MIDI note range
+const OCTAVES: std::ops::RangeInclusive<i32> = -1..=8;
+
+// Terminals that cannot report key releases get notes of this length
+const NOTE_LENGTH: Duration = Duration::from_millis(400);
+
+// How often the display is refreshed and held notes are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+const VELOCITY: u8 = 100;
+
+// Keyboard notes go out on the first MIDI channel
+const CHANNEL: u8 = 0;
+
+/// Play `voices` from the keyboard until Esc or Ctrl-C is pressed, or until `is_playing`
+/// returns false. `z` and `x` shift the keyboard an octave down or up.
+pub fn run(mut voices: Voices, is_playing: impl Fn() -> bool) -> Result<(), anyhow::Error> {
+    println!("Keys a w s e d f t g y h u j k o l p ; play from C; z/x shift octave; Esc quits");
+
+    let terminal = RawTerminal::enable()?;
+    let mut keyboard = Keyboard {
+        octave: DEFAULT_OCTAVE,
+        active: BTreeMap::new(),
+        reports_releases: terminal.enhanced,
+    };
+    keyboard.draw()?;
+
+    while is_playing() {
+        if event::poll(POLL_INTERVAL)?
+            && let Event::Key(key) = event::read()?
+            && !keyboard.handle(&mut voices, key)
+        {
+            break;
+        }
+
+        let now = Instant::now();
+        keyboard.active.retain(|&note, (_, release)| match release {
+            Some(at) if *at <= now => {
+                voices.note_off(CHANNEL, note);
+                false
+            }
+            _ => true,
+        });
+        keyboard.draw()?;
+    }
+    Ok(())
+}
+
+struct Keyboard {
+    octave: i32,
+    // Each sounding note with its key and the time it is let go, unless that waits for
+    // the key release
+    active: BTreeMap<u8, (char, Option<Instant>)>,
+    reports_releases: bool,
+}
+
+impl Keyboard {
+    // Returns false when the user asked to quit
+    fn handle(&mut self, voices: &mut Voices, key: KeyEvent) -> bool {
+        let quit = key.code == KeyCode::Esc
+            || key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+        if quit {
+            return false;
+        }
+        let KeyCode::Char(c) = key.code else {
+            return true;
+        };
+        let c = c.to_ascii_lowercase();
+
+        match (key.kind, c) {
+            (KeyEventKind::Press, 'z') => self.shift(-1),
+            (KeyEventKind::Press, 'x') => self.shift(1),
+            (KeyEventKind::Press, _) => {
+                // Without release events the note is let go after a fixed time
+                let release = (!self.reports_releases).then(|| Instant::now() + NOTE_LENGTH);
+                // Such terminals also send auto-repeat as more presses, which only keep
+                // the held note going
+                if let Some((_, held)) = self.active.values_mut().find(|(key, _)| *key == c) {
+                    *held = release;
+                } else if let Some(note) = self.note(c) {
+                    voices.note_on(CHANNEL, note, VELOCITY);
+                    self.active.insert(note, (c, release));
+                }
+            }
+            // Look the note up by key, as the octave may have changed since it was pressed
+            (KeyEventKind::Release, _) => self.active.retain(|&note, (key, _)| {
+                if *key == c {
+                    voices.note_off(CHANNEL, note);
+                }
+                *key != c
+            }),
+            // Auto-repeat would otherwise pluck the string over and over
+            (KeyEventKind::Repeat, _) => {}
+        }
+        true
+    }
+
+    fn shift(&mut self, octaves: i32) {
+        self.octave = (self.octave + octaves).clamp(*OCTAVES.start(), *OCTAVES.end());
+    }
+
+    // MIDI note of a key at the current octave
+    fn note(&self, key: char) -> Option<u8> {
+        let index = KEYS.iter().position(|&k| k == key)? as i32;
+        u8::try_from((self.octave + 1) * 12 + index)
+            .ok()
+            .filter(|&note| note <= 127)
+    }
+
+    fn draw(&self) -> Result<(), anyhow::Error> {
+        let notes: Vec<String> = self.active.keys().map(|&note| note_name(note)).collect();
+        let mut stdout = std::io::stdout();
+        queue!(stdout, terminal::Clear(ClearType::CurrentLine))?;
+        write!(
+            stdout,
+            "\rOctave {:>2} | {}",
+            self.octave,
+            if notes.is_empty() {
+                "-".to_string()
+            } else {
+                notes.join(" ")
+            }
+        )?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+// Scientific pitch name, with C4 as MIDI note 60
+fn note_name(note: u8) -> String {
+    let octave = note as i32 / 12 - 1;
+    format!("{}{}", NOTE_NAMES[note as usize % 12], octave)
+}
+
+/// Raw mode for the lifetime of the value, with key release reporting where the
+/// terminal supports it.
+struct RawTerminal {
+    enhanced: bool,
+}
+
+impl RawTerminal {
+    fn enable() -> Result<Self, anyhow::Error> {
+        terminal::enable_raw_mode()?;
+        let mut raw = RawTerminal { enhanced: false };
+        if terminal::supports_keyboard_enhancement().unwrap_or(false) {
+            execute!(
+                std::io::stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+            raw.enhanced = true;
+        }
+        Ok(raw)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        if self.enhanced {
+            let _ = execute!(std::io::stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = terminal::disable_raw_mode();
+        println!();
+    }
+}
@@ -0,0 +1,44 @@
+//! Plucked guitar synthesis on top of fundsp.
+//!
+//! A [`Score`] of notes is played by an [`Instrument`] through an [`EffectsChain`];
+//! [`Synth`] ties the three together and can render to a [`fundsp::hacker::Wave`]
+//! or play live through cpal with [`playback::play`]. The [`live`] module plays an
+//! instrument from incoming MIDI instead of a score, and [`keyboard`] from the computer
+//! keyboard.
+
+#![allow(clippy::precedence)]
+
+pub mod articulation;
+pub mod channels;
+pub mod chord;
+pub mod convolution;
+pub mod device;
+pub mod effects;
+pub mod fretboard;
+pub mod instrument;
+pub mod keyboard;
+pub mod live;
+pub mod midi;
+pub mod playback;
+pub mod preset;
+pub mod render;
+pub mod score;
+pub mod synth;
+pub mod tempo;
+pub mod tuning;
+
+pub use articulation::{Articulation, Technique};
+pub use channels::Upmix;
+pub use chord::{Arpeggio, Chord, Strum, StrumDirection};
+pub use convolution::{Convolver, ImpulseResponse};
+pub use effects::{DelayTime, Effect, EffectsChain, EqBand, Stage};
+pub use fretboard::{Fretboard, Shape};
+pub use instrument::{
+    Guitar, Instrument, InstrumentKind, Karplus, NoteParams, Organ, Resonance, VelocityRange,
+};
+pub use preset::Preset;
+pub use render::BitDepth;
+pub use score::{Note, Score, ScoreBuilder};
+pub use synth::{Synth, SynthBuilder};
+pub use tempo::{Division, TempoMap, TimeSignature};
+pub use tuning::{KeyboardMapping, Tuning};
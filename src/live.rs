@@ -0,0 +1,184 @@
+//! Playing an instrument in real time from incoming MIDI notes.
+
+use crate::articulation::Technique;
+use crate::effects::EffectsChain;
+use crate::instrument::{Instrument, NoteParams};
+use crate::midi::PERCUSSION_CHANNEL;
+use crate::tempo::DEFAULT_TEMPO;
+use crate::tuning::Tuning;
+use anyhow::anyhow;
+use fundsp::hacker::*;
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use midly::MidiMessage;
+use midly::live::LiveEvent;
+use std::collections::HashMap;
+
+/// Name of the MIDI client, and of the virtual input port where the platform has them.
+pub const CLIENT_NAME: &str = "sound-test";
+
+// Channel mode message that releases every held note
+const ALL_NOTES_OFF: u8 = 123;
+
+/// The note-triggering front end of a live graph. Notes start when they arrive and
+/// ring until their note-off, followed by the instrument release.
+pub struct Voices {
+    sequencer: Sequencer,
+    instrument: Box<dyn Instrument>,
+    tuning: Tuning,
+    held: HashMap<(u8, u8), EventId>,
+}
+
+impl Voices {
+    /// Start a note, ending any note still held on the same channel and key. Keys the
+    /// tuning leaves unused play nothing.
+    pub fn note_on(&mut self, channel: u8, key: u8, velocity: u8) {
+        self.note_off(channel, key);
+        let Some(frequency) = self.tuning.frequency(key as f64) else {
+            return;
+        };
+        let voice = self.instrument.note(&NoteParams {
+            frequency: frequency as f32,
+            velocity: velocity as f32 / 127.0,
+            duration: f64::INFINITY,
+            articulations: Vec::new(),
+            technique: Technique::Open,
+            pick_position: None,
+        });
+        let id = self
+            .sequencer
+            .push_relative(0.0, f64::INFINITY, Fade::Smooth, 0.01, 0.1, voice);
+        self.held.insert((channel, key), id);
+    }
+
+    /// Release a held note; it keeps sounding for the instrument release.
+    pub fn note_off(&mut self, channel: u8, key: u8) {
+        if let Some(id) = self.held.remove(&(channel, key)) {
+            let release = self.instrument.release().max(0.1);
+            self.sequencer.edit_relative(id, release, 0.1);
+        }
+    }
+
+    /// Release every held note on a channel.
+    pub fn all_notes_off(&mut self, channel: u8) {
+        let keys: Vec<u8> = self
+            .held
+            .keys()
+            .filter(|(c, _)| *c == channel)
+            .map(|(_, key)| *key)
+            .collect();
+        for key in keys {
+            self.note_off(channel, key);
+        }
+    }
+
+    /// Handle one raw MIDI message. Anything other than notes is ignored.
+    pub fn handle(&mut self, message: &[u8]) {
+        let Ok(LiveEvent::Midi { channel, message }) = LiveEvent::parse(message) else {
+            return;
+        };
+        let channel = channel.as_int();
+        if channel == PERCUSSION_CHANNEL {
+            return;
+        }
+        match message {
+            // A note-on with zero velocity is a note-off
+            MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                self.note_on(channel, key.as_int(), vel.as_int())
+            }
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                self.note_off(channel, key.as_int())
+            }
+            MidiMessage::Controller { controller, .. } if controller == ALL_NOTES_OFF => {
+                self.all_notes_off(channel)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Build a stereo graph at `sample_rate` that plays whatever notes are sent to the
+/// returned [`Voices`] in the tuning, through the effects chain.
+pub fn create_live_graph(
+    instrument: Box<dyn Instrument>,
+    tuning: Tuning,
+    effects: &EffectsChain,
+    sample_rate: f64,
+) -> (Net, Voices) {
+    let mut net = Net::new(0, 2);
+
+    // Units pushed from the front end take its sample rate, so set it before splitting
+    let mut sequencer = Sequencer::new(false, 2);
+    sequencer.set_sample_rate(sample_rate);
+    let sequencer_id = net.push(Box::new(sequencer.backend()));
+
+    let output_id = effects.connect(&mut net, sequencer_id, DEFAULT_TEMPO);
+    net.pipe_output(output_id);
+
+    let voices = Voices {
+        sequencer,
+        instrument,
+        tuning,
+        held: HashMap::new(),
+    };
+    (net, voices)
+}
+
+/// Print the MIDI input ports that can be passed to [`connect`].
+pub fn list_ports() -> Result<(), anyhow::Error> {
+    let input = MidiInput::new(CLIENT_NAME)?;
+    for port in input.ports() {
+        println!("{}", input.port_name(&port)?);
+    }
+    Ok(())
+}
+
+/// Feed incoming MIDI to `voices` until the returned connection is dropped.
+///
+/// With a port name, connects to the first input port whose name contains it, ignoring
+/// case. Without one, opens a virtual input port named [`CLIENT_NAME`] that other
+/// programs can connect to, e.g. with `aconnect` on Linux.
+pub fn connect(
+    port: Option<&str>,
+    mut voices: Voices,
+) -> Result<MidiInputConnection<()>, anyhow::Error> {
+    let mut input = MidiInput::new(CLIENT_NAME)?;
+    input.ignore(Ignore::All);
+    let callback = move |_: u64, message: &[u8], _: &mut ()| voices.handle(message);
+
+    let Some(name) = port else {
+        return connect_virtual(input, callback);
+    };
+    let needle = name.to_lowercase();
+    let port = input
+        .ports()
+        .into_iter()
+        .find(|port| {
+            input
+                .port_name(port)
+                .is_ok_and(|n| n.to_lowercase().contains(&needle))
+        })
+        .ok_or_else(|| anyhow!("No MIDI input port matching '{name}'; try --list-midi-ports"))?;
+    input
+        .connect(&port, CLIENT_NAME, callback, ())
+        .map_err(|err| anyhow!("Could not connect to MIDI port '{name}': {err}"))
+}
+
+#[cfg(unix)]
+fn connect_virtual(
+    input: MidiInput,
+    callback: impl FnMut(u64, &[u8], &mut ()) + Send + 'static,
+) -> Result<MidiInputConnection<()>, anyhow::Error> {
+    use midir::os::unix::VirtualInput;
+
+    input
+        .create_virtual(CLIENT_NAME, callback, ())
+        .map_err(|err| anyhow!("Could not create virtual MIDI port: {err}"))
+}
+
+#[cfg(not(unix))]
+fn connect_virtual(
+    _input: MidiInput,
+    _callback: impl FnMut(u64, &[u8], &mut ()) + Send + 'static,
+) -> Result<MidiInputConnection<()>, anyhow::Error> {
+    anyhow::bail!("Virtual MIDI ports are not supported here; choose one with --midi-port")
+}
@@ -1,9 +1,10 @@
-#![allow(clippy::precedence)]
-
+use anyhow::Context;
 use clap::Parser;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{FromSample, SizedSample};
-use fundsp::hacker::*;
+use sound_test::playback::{self, PlaybackOptions};
+use sound_test::{
+    BitDepth, EffectsChain, ImpulseResponse, Instrument, InstrumentKind, KeyboardMapping, Preset,
+    Score, Synth, Tuning, Upmix, device, keyboard, live, midi, render,
+};
 
 #[derive(Parser)]
 #[command(name = "sound-test")]
@@ -11,154 +12,325 @@ use fundsp::hacker::*;
 struct Args {
     #[arg(short = 'o', long = "output", help = "Output WAV file path")]
     output: Option<String>,
+
+    #[arg(
+        long = "score",
+        help = "Text score file to play instead of the C major scale"
+    )]
+    score: Option<String>,
+
+    #[arg(
+        long = "midi",
+        conflicts_with = "score",
+        help = "Standard MIDI File to play instead of the C major scale"
+    )]
+    midi: Option<String>,
+
+    #[arg(
+        long = "instrument",
+        value_enum,
+        default_value_t = InstrumentKind::Guitar,
+        help = "Voice used to play the notes"
+    )]
+    instrument: InstrumentKind,
+
+    #[arg(
+        long = "tuning",
+        value_name = "NAME|FILE",
+        help = "Tuning of the notes: equal (the default), just, pythagorean, meantone or a Scala .scl file"
+    )]
+    tuning: Option<String>,
+
+    #[arg(
+        long = "kbm",
+        value_name = "FILE",
+        help = "Scala .kbm keyboard mapping for the tuning"
+    )]
+    kbm: Option<String>,
+
+    #[arg(
+        long = "reference",
+        value_name = "HZ",
+        help = "Frequency of the reference key, A4 unless a keyboard mapping says otherwise"
+    )]
+    reference: Option<f64>,
+
+    #[arg(
+        long = "tuning-root",
+        value_name = "NOTE",
+        conflicts_with = "kbm",
+        help = "Note the tuning's scale starts on, e.g. D4 for just intonation in D; defaults to C4"
+    )]
+    tuning_root: Option<String>,
+
+    #[arg(
+        long = "duration",
+        help = "Length in seconds; by default playback ends once the tail after the last note falls silent"
+    )]
+    duration: Option<f64>,
+
+    #[arg(
+        long = "sample-rate",
+        help = "Sample rate in Hz; defaults to 44100 for WAV export and to the device default for playback"
+    )]
+    sample_rate: Option<u32>,
+
+    #[arg(
+        long = "bit-depth",
+        value_enum,
+        default_value_t = BitDepth::Float32,
+        help = "Sample format for WAV export; 16-bit output is dithered"
+    )]
+    bit_depth: BitDepth,
+
+    #[arg(
+        long = "list-devices",
+        help = "List audio hosts, output devices and their configs"
+    )]
+    list_devices: bool,
+
+    #[arg(long = "host", help = "Audio host to play through, e.g. ALSA or JACK")]
+    host: Option<String>,
+
+    #[arg(long = "device", help = "Output device name, or part of it")]
+    device: Option<String>,
+
+    #[arg(long = "buffer-size", help = "Playback buffer size in frames")]
+    buffer_size: Option<u32>,
+
+    #[arg(
+        long = "upmix",
+        value_enum,
+        default_value_t = Upmix::Silent,
+        help = "How center, LFE and surround channels are fed on multichannel devices"
+    )]
+    upmix: Upmix,
+
+    #[arg(
+        long = "live",
+        conflicts_with_all = ["output", "score", "midi"],
+        help = "Play notes from MIDI input in real time until interrupted"
+    )]
+    live: bool,
+
+    #[arg(
+        long = "keyboard",
+        conflicts_with_all = ["output", "score", "midi", "live"],
+        help = "Play notes from the computer keyboard in the terminal"
+    )]
+    keyboard: bool,
+
+    #[arg(
+        long = "midi-port",
+        requires = "live",
+        help = "MIDI input port name, or part of it; by default a virtual port is opened"
+    )]
+    midi_port: Option<String>,
+
+    #[arg(long = "list-midi-ports", help = "List MIDI input ports")]
+    list_midi_ports: bool,
+
+    #[arg(
+        long = "preset",
+        help = "TOML preset with the guitar voice and effects chain"
+    )]
+    preset: Option<String>,
+
+    #[arg(
+        long = "dump-preset",
+        help = "Print the preset in use, the built-in defaults without --preset, as TOML"
+    )]
+    dump_preset: bool,
+
+    #[arg(
+        long = "body-ir",
+        value_name = "FILE",
+        help = "WAV impulse response of a guitar body, convolved with the strings in place of the preset body resonances"
+    )]
+    body_ir: Option<String>,
+
+    #[arg(
+        long = "effect",
+        value_name = "NAME[:KEY=VALUE,...]",
+        help = "Master effect stage, repeated in chain order; replaces the preset chain. One of chorus, flanger, phaser, delay, pingpong, reverb, convolution, eq, compressor or limiter, e.g. reverb:room_size=20,time=2, delay:time=1/8d, convolution:response=room.wav,pre_delay=0.03,mix=0.4, 'eq:low_gain=3,bands=[{frequency=800,gain=-4,q=2}]' or delay:bypass"
+    )]
+    effects: Vec<String>,
+
+    #[arg(
+        long = "no-effects",
+        conflicts_with = "effects",
+        help = "Play the instrument without any master effects"
+    )]
+    no_effects: bool,
 }
 
-fn main() {
+fn main() -> Result<(), anyhow::Error> {
     let args = Args::parse();
 
-    if let Some(output_file) = args.output {
-        save_to_wav(&output_file);
-        return;
+    if args.list_devices {
+        return device::list_devices();
+    }
+    if args.list_midi_ports {
+        return live::list_ports();
     }
 
-    let host = cpal::default_host();
-
-    let device = host
-        .default_output_device()
-        .expect("Failed to find a default output device");
-    let config = device.default_output_config().unwrap();
-
-    match config.sample_format() {
-        cpal::SampleFormat::F32 => run::<f32>(&device, &config.into()).unwrap(),
-        cpal::SampleFormat::I16 => run::<i16>(&device, &config.into()).unwrap(),
-        cpal::SampleFormat::U16 => run::<u16>(&device, &config.into()).unwrap(),
-        _ => panic!("Unsupported format"),
+    let mut preset = match &args.preset {
+        Some(path) => Some(Preset::load(std::path::Path::new(path))?),
+        None => None,
+    };
+    // An effects chain from the command line takes the place of the preset one
+    if args.no_effects || !args.effects.is_empty() {
+        let effects = EffectsChain::parse(&args.effects)?;
+        preset.get_or_insert_with(Preset::default).effects = Some(effects);
     }
-}
+    if let Some(path) = &args.body_ir {
+        if args.instrument != InstrumentKind::Guitar {
+            anyhow::bail!("--body-ir only applies to the guitar instrument");
+        }
+        let response = ImpulseResponse::load(std::path::Path::new(path))?;
+        preset
+            .get_or_insert_with(Preset::default)
+            .guitar
+            .body_response = Some(response);
+    }
+    if args.dump_preset {
+        let mut preset = preset.unwrap_or_default();
+        preset.effects.get_or_insert_with(EffectsChain::default);
+        print!("{}", preset.to_toml());
+        return Ok(());
+    }
+    // The preset voice stands in for the built-in guitar
+    let instrument = || -> Box<dyn Instrument> {
+        match &preset {
+            Some(preset) if args.instrument == InstrumentKind::Guitar => {
+                Box::new(preset.guitar.clone())
+            }
+            _ => args.instrument.build(),
+        }
+    };
+    // Live playback keeps its short limiter look-ahead unless a chain was given
+    let chain = preset.as_ref().and_then(|preset| preset.effects.clone());
+    let effects = chain.clone().unwrap_or_default();
+    let live_effects = chain.unwrap_or_else(EffectsChain::live);
+
+    let tuning = load_tuning(&args)?;
+
+    let score = match (&args.score, &args.midi) {
+        (Some(path), _) => Score::load(std::path::Path::new(path))?,
+        (_, Some(path)) => midi::load(std::path::Path::new(path))?,
+        (None, None) => Score::c_major_scale(),
+    };
+    let synth = Synth::builder()
+        .score(score)
+        .boxed_instrument(instrument())
+        .tuning(tuning.clone())
+        .effects(effects)
+        .build();
 
-fn create_audio_graph() -> Net {
-    // Use Net for dynamic sequencing
-    let mut net = Net::new(0, 2);
-
-    // BPM 120 = 0.5 seconds per quarter note
-    let note_duration = 0.5;
-
-    // C major scale starting from C4 (MIDI note 60)
-    let c_major_scale = [60.0, 62.0, 64.0, 65.0, 67.0, 69.0, 71.0, 72.0];
-
-    // Create a sequencer to play notes one by one
-    let mut sequencer = Sequencer::new(false, 2);
-
-    // Add each note to the sequencer with proper timing
-    for (i, &midi_note) in c_major_scale.iter().enumerate() {
-        let start_time = i as f64 * note_duration;
-        let end_time = start_time + note_duration + 1.0; // Extra time for natural decay
-
-        // Create individual guitar note using white noise excitation
-        let guitar_note = white()
-            * envelope(|t| if t < 0.01 { 1.0 } else { 0.0 })
-            >> pluck(midi_hz(midi_note), 0.996, 0.3)
-            >> (pass() &
-                bandpass_hz(110.0, 1.5) * 0.15 &  // Low body resonance
-                bandpass_hz(200.0, 2.0) * 0.25 &  // Primary body resonance
-                bandpass_hz(400.0, 2.5) * 0.2 &   // Mid-body resonance
-                bandpass_hz(800.0, 3.0) * 0.1)    // High frequency brightness
-            >> lowpass_hz(6000.0, 1.0)
-            >> dcblock()
-            * 0.7
-            >> pan(0.0);
-
-        // Add to sequencer - each note plays sequentially
-        sequencer.push(
-            start_time,
-            end_time,
-            Fade::Smooth,
-            0.01, // 10ms fade in
-            0.1,  // 100ms fade out
-            Box::new(guitar_note),
+    if let Some(output_file) = args.output {
+        let sample_rate = args.sample_rate.unwrap_or(44100) as f64;
+        return save_to_wav(
+            &output_file,
+            &synth,
+            args.duration,
+            sample_rate,
+            args.bit_depth,
         );
     }
 
-    // Convert sequencer to net
-    let sequencer_id = net.push(Box::new(sequencer));
-
-    // Add final effects
-    let chorus_id = net.push(Box::new(
-        chorus(0, 0.0, 0.002, 0.1) | chorus(1, 0.0, 0.002, 0.1),
-    ));
-    let reverb_id = net.push(Box::new(reverb_stereo(0.8, 0.3, 0.02)));
-    let limiter_id = net.push(Box::new(limiter_stereo(0.9, 2.0)));
+    let host = device::select_host(args.host.as_deref())?;
+    let device = device::select_device(&host, args.device.as_deref())?;
+    let supported = device::select_config(&device, args.sample_rate)?;
+    let config = device::stream_config(&supported, args.buffer_size)?;
+
+    let options = PlaybackOptions {
+        duration: args.duration,
+        upmix: args.upmix,
+    };
+
+    if args.keyboard {
+        let (net, voices) = live::create_live_graph(
+            instrument(),
+            tuning,
+            &live_effects,
+            config.sample_rate.0 as f64,
+        );
+        // The stream plays on its own thread while this one reads the keyboard
+        let format = supported.sample_format();
+        let player = std::thread::spawn(move || {
+            playback::play_net(&device, format, &config, net, None, options)
+        });
+        keyboard::run(voices, || !player.is_finished())?;
+        if player.is_finished() {
+            return player.join().expect("playback thread panicked");
+        }
+        return Ok(());
+    }
 
-    net.pipe_all(sequencer_id, chorus_id);
-    net.pipe_all(chorus_id, reverb_id);
-    net.pipe_all(reverb_id, limiter_id);
-    net.pipe_output(limiter_id);
+    if args.live {
+        let (net, voices) = live::create_live_graph(
+            instrument(),
+            tuning,
+            &live_effects,
+            config.sample_rate.0 as f64,
+        );
+        // Notes arrive for as long as the connection is alive
+        let _connection = live::connect(args.midi_port.as_deref(), voices)?;
+        if args.midi_port.is_none() {
+            println!("Listening on virtual MIDI port '{}'", live::CLIENT_NAME);
+        }
+        return playback::play_net(
+            &device,
+            supported.sample_format(),
+            &config,
+            net,
+            None,
+            options,
+        );
+    }
 
-    net
+    playback::play(&device, supported.sample_format(), &config, &synth, options)
 }
 
-fn save_to_wav(filename: &str) {
-    let sample_rate = 44100.0;
-    // Duration for 8 quarter notes at BPM 120 = 8 * 0.5 = 4 seconds + some extra for decay
-    let duration = 6.0;
-
-    let mut c = create_audio_graph();
-
-    let wave = Wave::render(sample_rate, duration, &mut c);
-    let path = std::path::Path::new(filename);
-    wave.save_wav32(path)
-        .expect(&format!("Could not save {}", filename));
-
-    println!("Saved C major scale to {}", filename);
+// A built-in tuning or Scala scale, with the keyboard mapping, reference and root applied
+fn load_tuning(args: &Args) -> Result<Tuning, anyhow::Error> {
+    let mut tuning = match args.tuning.as_deref() {
+        None => Tuning::default(),
+        Some(name) => match Tuning::from_name(name) {
+            Some(tuning) => tuning,
+            None => Tuning::load_scala(std::path::Path::new(name))?,
+        },
+    };
+    if let Some(path) = &args.kbm {
+        tuning = tuning.with_mapping(KeyboardMapping::load(std::path::Path::new(path))?);
+    }
+    if let Some(root) = &args.tuning_root {
+        let key = sound_test::score::parse_pitch(root)
+            .with_context(|| format!("Invalid tuning root '{root}'"))?;
+        tuning = tuning.with_root(key.round() as i32);
+    }
+    if let Some(frequency) = args.reference {
+        if !(frequency.is_finite() && frequency > 0.0) {
+            anyhow::bail!("Invalid reference frequency {frequency}");
+        }
+        tuning = tuning.with_reference(frequency);
+    }
+    Ok(tuning)
 }
 
-fn run<T>(device: &cpal::Device, config: &cpal::StreamConfig) -> Result<(), anyhow::Error>
-where
-    T: SizedSample + FromSample<f32>,
-{
-    let sample_rate = config.sample_rate.0 as f64;
-    let channels = config.channels as usize;
-
-    let mut c = create_audio_graph();
-
-    c.set_sample_rate(sample_rate);
-    c.allocate();
-
-    let mut next_value = move || c.get_stereo();
-
-    let err_fn = |err| eprintln!("an error occurred on stream: {err}");
-
-    let stream = device.build_output_stream(
-        config,
-        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-            write_data(data, channels, &mut next_value)
-        },
-        err_fn,
-        None,
-    )?;
-    stream.play()?;
+fn save_to_wav(
+    filename: &str,
+    synth: &Synth,
+    duration: Option<f64>,
+    sample_rate: f64,
+    bit_depth: BitDepth,
+) -> Result<(), anyhow::Error> {
+    let mut wave = synth.render(sample_rate, duration);
+    let path = std::path::Path::new(filename);
+    render::save(&mut wave, path, bit_depth)
+        .with_context(|| format!("Could not save {}", filename))?;
 
-    // Play for 6 seconds to hear the complete C major scale
-    std::thread::sleep(std::time::Duration::from_millis(6000));
+    println!("Saved score to {}", filename);
 
     Ok(())
 }
-
-fn write_data<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> (f32, f32))
-where
-    T: SizedSample + FromSample<f32>,
-{
-    for frame in output.chunks_mut(channels) {
-        let sample = next_sample();
-        let left = T::from_sample(sample.0);
-        let right: T = T::from_sample(sample.1);
-
-        for (channel, sample) in frame.iter_mut().enumerate() {
-            if channel & 1 == 0 {
-                *sample = left;
-            } else {
-                *sample = right;
-            }
-        }
-    }
-}
@@ -0,0 +1,304 @@
+//! Standard MIDI File import.
+
+use crate::articulation::{Articulation, Technique};
+use crate::score::{Note, Score};
+use crate::tempo::{TempoMap, TimeSignature};
+use anyhow::{Context, bail};
+use midly::{Format, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::collections::HashMap;
+use std::path::Path;
+
+// General MIDI percussion channel, which makes no sense on a pitched voice
+pub(crate) const PERCUSSION_CHANNEL: u8 = 9;
+
+// Pitch bend range in semitones either way, the General MIDI default
+const PITCH_BEND_RANGE: f64 = 2.0;
+
+/// Load a Standard MIDI File (type 0 or 1) into a score, following its tempo map.
+/// Pitch bend messages become [`Articulation::PitchBend`]s on the notes of their channel.
+pub fn load(path: &Path) -> Result<Score, anyhow::Error> {
+    let bytes = std::fs::read(path)
+        .with_context(|| format!("Could not read MIDI file {}", path.display()))?;
+    parse(&bytes).with_context(|| format!("Invalid MIDI file {}", path.display()))
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Score, anyhow::Error> {
+    let smf = Smf::parse(bytes)?;
+    if smf.header.format == Format::Sequential {
+        bail!("type 2 (sequential) MIDI files are not supported");
+    }
+
+    // Absolute tick positions of every event, with tempo and time signature changes
+    // collected from all tracks
+    let mut tempo_changes = Vec::new();
+    let mut signature_changes = Vec::new();
+    let mut messages = Vec::new();
+    for track in &smf.tracks {
+        let mut tick = 0u64;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                    tempo_changes.push((tick, tempo.as_int()));
+                }
+                // The denominator is stored as a power of two
+                TrackEventKind::Meta(MetaMessage::TimeSignature(beats, unit, _, _))
+                    if beats > 0 && unit < 32 =>
+                {
+                    let unit = 1 << unit;
+                    signature_changes.push((
+                        tick,
+                        TimeSignature {
+                            beats: beats as u32,
+                            unit,
+                        },
+                    ));
+                }
+                TrackEventKind::Midi { channel, message } => {
+                    messages.push((tick, channel.as_int(), message));
+                }
+                _ => {}
+            }
+        }
+    }
+    // Stable sort keeps the file order of events on the same tick
+    messages.sort_by_key(|&(tick, _, _)| tick);
+
+    let last_tick = messages.last().map_or(0, |&(tick, _, _)| tick);
+    let clock = Clock::new(smf.header.timing, tempo_changes, signature_changes);
+
+    // Pending note-ons per channel and key, in the order they were struck
+    let mut pending: HashMap<(u8, u8), Vec<PendingNote>> = HashMap::new();
+    let mut notes = Vec::new();
+    // Current pitch bend of each channel in semitones
+    let mut bends: HashMap<u8, f64> = HashMap::new();
+
+    for (tick, channel, message) in messages {
+        if channel == PERCUSSION_CHANNEL {
+            continue;
+        }
+        match message {
+            MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                // A note struck while the channel is bent starts out bent
+                let bends = match bends.get(&channel) {
+                    Some(&semitones) if semitones != 0.0 => vec![(tick, semitones)],
+                    _ => Vec::new(),
+                };
+                pending
+                    .entry((channel, key.as_int()))
+                    .or_default()
+                    .push(PendingNote {
+                        tick,
+                        velocity: vel.as_int(),
+                        bends,
+                    });
+            }
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                let Some(struck) = pending.get_mut(&(channel, key.as_int())) else {
+                    continue;
+                };
+                if struck.is_empty() {
+                    continue;
+                }
+                let note = struck.remove(0);
+                notes.push(clock.note(note, tick, key.as_int()));
+            }
+            MidiMessage::PitchBend { bend } => {
+                let semitones = bend.as_f64() * PITCH_BEND_RANGE;
+                bends.insert(channel, semitones);
+                for (_, struck) in pending.iter_mut().filter(|((c, _), _)| *c == channel) {
+                    for note in struck {
+                        note.bends.push((tick, semitones));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Notes that are never released last until the final event of the file
+    for ((_, key), struck) in pending {
+        for note in struck {
+            notes.push(clock.note(note, last_tick, key));
+        }
+    }
+
+    notes.sort_by(|a, b| a.start.total_cmp(&b.start));
+    Ok(Score {
+        notes,
+        tempo: clock.tempo,
+    })
+}
+
+// A note-on waiting for its note-off, with the pitch bends of its channel since
+struct PendingNote {
+    tick: u64,
+    velocity: u8,
+    bends: Vec<(u64, f64)>,
+}
+
+// Converts MIDI ticks to seconds
+struct Clock {
+    timing: Timing,
+    // Tempo and time signatures by beat; files in timecode keep the default map
+    tempo: TempoMap,
+}
+
+impl Clock {
+    fn new(
+        timing: Timing,
+        mut tempo_changes: Vec<(u64, u32)>,
+        mut signature_changes: Vec<(u64, TimeSignature)>,
+    ) -> Self {
+        let mut tempo = TempoMap::default();
+        if let Timing::Metrical(ticks_per_beat) = timing {
+            let ticks_per_beat = ticks_per_beat.as_int() as f64;
+            // Stable sorts, so the last of several changes on one tick wins
+            tempo_changes.sort_by_key(|&(tick, _)| tick);
+            for (tick, microseconds) in tempo_changes {
+                tempo.set_tempo(tick as f64 / ticks_per_beat, 60.0e6 / microseconds as f64);
+            }
+            signature_changes.sort_by_key(|&(tick, _)| tick);
+            for (tick, signature) in signature_changes {
+                tempo.set_time_signature(tick as f64 / ticks_per_beat, signature);
+            }
+        }
+        Clock { timing, tempo }
+    }
+
+    fn seconds(&self, tick: u64) -> f64 {
+        match self.timing {
+            Timing::Metrical(ticks_per_beat) => self
+                .tempo
+                .seconds(tick as f64 / ticks_per_beat.as_int() as f64),
+            Timing::Timecode(fps, subframes) => {
+                tick as f64 / (fps.as_f32() as f64 * subframes as f64)
+            }
+        }
+    }
+
+    fn note(&self, note: PendingNote, end_tick: u64, key: u8) -> Note {
+        let start = self.seconds(note.tick);
+        let articulations = note
+            .bends
+            .into_iter()
+            .map(|(tick, semitones)| Articulation::PitchBend {
+                time: self.seconds(tick) - start,
+                semitones,
+            })
+            .collect();
+        Note {
+            start,
+            duration: self.seconds(end_tick) - start,
+            pitch: key as f64,
+            velocity: note.velocity as f32 / 127.0,
+            string: None,
+            articulations,
+            technique: Technique::Open,
+            pick_position: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::{Header, PitchBend, TrackEvent};
+
+    // A single track file at 480 ticks per beat, from events after a delta in ticks
+    fn file(events: Vec<(u32, TrackEventKind)>) -> Vec<u8> {
+        let mut smf = Smf::new(Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(480.into()),
+        ));
+        let mut track: Vec<TrackEvent> = events
+            .into_iter()
+            .map(|(delta, kind)| TrackEvent {
+                delta: delta.into(),
+                kind,
+            })
+            .collect();
+        track.push(TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        smf.tracks.push(track);
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn midi(channel: u8, message: MidiMessage) -> TrackEventKind<'static> {
+        TrackEventKind::Midi {
+            channel: channel.into(),
+            message,
+        }
+    }
+
+    fn note_on(channel: u8, key: u8, vel: u8) -> TrackEventKind<'static> {
+        midi(
+            channel,
+            MidiMessage::NoteOn {
+                key: key.into(),
+                vel: vel.into(),
+            },
+        )
+    }
+
+    fn tempo(microseconds_per_beat: u32) -> TrackEventKind<'static> {
+        TrackEventKind::Meta(MetaMessage::Tempo(microseconds_per_beat.into()))
+    }
+
+    #[test]
+    fn notes_follow_the_tempo_map() {
+        let bytes = file(vec![
+            (0, tempo(500_000)),
+            (0, note_on(0, 60, 100)),
+            (0, note_on(PERCUSSION_CHANNEL, 36, 100)),
+            // A note-on without velocity releases the note
+            (480, note_on(0, 60, 0)),
+            (0, tempo(1_000_000)),
+            (0, note_on(0, 64, 127)),
+            (
+                240,
+                midi(
+                    0,
+                    MidiMessage::PitchBend {
+                        bend: PitchBend::from_f64(0.5),
+                    },
+                ),
+            ),
+            (
+                240,
+                midi(
+                    0,
+                    MidiMessage::NoteOff {
+                        key: 64.into(),
+                        vel: 0.into(),
+                    },
+                ),
+            ),
+            (0, note_on(PERCUSSION_CHANNEL, 36, 0)),
+        ]);
+        let score = parse(&bytes).unwrap();
+        let notes: Vec<(f64, f64, f64, f32)> = score
+            .notes
+            .iter()
+            .map(|note| (note.start, note.duration, note.pitch, note.velocity))
+            .collect();
+        // A beat at 120 BPM, then a beat at 60 BPM
+        assert_eq!(
+            notes,
+            [(0.0, 0.5, 60.0, 100.0 / 127.0), (0.5, 1.0, 64.0, 1.0)]
+        );
+        assert!(score.notes[0].articulations.is_empty());
+        assert_eq!(
+            score.notes[1].articulations,
+            [Articulation::PitchBend {
+                time: 0.5,
+                semitones: 1.0,
+            }]
+        );
+        assert!(parse(b"MThd").is_err());
+    }
+}
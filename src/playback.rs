@@ -0,0 +1,173 @@
+//! Live playback of a synth through a cpal output stream.
+
+use crate::channels::{ChannelMap, Upmix};
+use crate::render::TailDetector;
+use crate::synth::Synth;
+use anyhow::{Context, bail};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{FromSample, I24, SampleFormat, SizedSample};
+use fundsp::hacker::*;
+use std::sync::mpsc::{RecvTimeoutError, sync_channel};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlaybackOptions {
+    /// Fixed playback length; by default playback ends once the tail has gone silent.
+    pub duration: Option<f64>,
+    pub upmix: Upmix,
+}
+
+/// Play the synth on `device` and block until playback has ended.
+/// Fails if the stream cannot be opened or the device goes away during playback.
+pub fn play(
+    device: &cpal::Device,
+    format: SampleFormat,
+    config: &cpal::StreamConfig,
+    synth: &Synth,
+    options: PlaybackOptions,
+) -> Result<(), anyhow::Error> {
+    play_net(
+        device,
+        format,
+        config,
+        synth.net(),
+        Some(synth.events_end()),
+        options,
+    )
+}
+
+/// Play any stereo graph on `device`. With `events_end`, playback ends once the tail after
+/// it has gone silent; without it, playback only stops at the duration or on an error.
+pub fn play_net(
+    device: &cpal::Device,
+    format: SampleFormat,
+    config: &cpal::StreamConfig,
+    c: Net,
+    events_end: Option<f64>,
+    options: PlaybackOptions,
+) -> Result<(), anyhow::Error> {
+    match format {
+        SampleFormat::I8 => run::<i8>(device, config, c, events_end, options),
+        SampleFormat::I16 => run::<i16>(device, config, c, events_end, options),
+        SampleFormat::I24 => run::<I24>(device, config, c, events_end, options),
+        SampleFormat::I32 => run::<i32>(device, config, c, events_end, options),
+        SampleFormat::I64 => run::<i64>(device, config, c, events_end, options),
+        SampleFormat::U8 => run::<u8>(device, config, c, events_end, options),
+        SampleFormat::U16 => run::<u16>(device, config, c, events_end, options),
+        SampleFormat::U32 => run::<u32>(device, config, c, events_end, options),
+        SampleFormat::U64 => run::<u64>(device, config, c, events_end, options),
+        SampleFormat::F32 => run::<f32>(device, config, c, events_end, options),
+        SampleFormat::F64 => run::<f64>(device, config, c, events_end, options),
+        format => bail!("Unsupported sample format '{format}' on the output device"),
+    }
+}
+
+fn run<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut c: Net,
+    events_end: Option<f64>,
+    options: PlaybackOptions,
+) -> Result<(), anyhow::Error>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let sample_rate = config.sample_rate.0 as f64;
+    let mut channel_map = ChannelMap::new(config.channels as usize, sample_rate, options.upmix);
+
+    c.set_sample_rate(sample_rate);
+    c.allocate();
+
+    // The callbacks report the end of playback and stream errors over a channel
+    let (sender, receiver) = sync_channel(16);
+    let mut detector = events_end.map(|events_end| TailDetector::new(sample_rate, events_end));
+    let mut next_value = {
+        let sender = sender.clone();
+        let mut finished = false;
+        move || {
+            let (left, right) = c.get_stereo();
+            let silent = detector
+                .as_mut()
+                .is_some_and(|detector| detector.tick(left, right));
+            if silent && !finished {
+                finished = true;
+                let _ = sender.try_send(PlaybackEvent::Finished);
+            }
+            (left, right)
+        }
+    };
+
+    let err_fn = move |err| {
+        let _ = sender.try_send(PlaybackEvent::Error(err));
+    };
+
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            write_data(data, &mut channel_map, &mut next_value)
+        },
+        err_fn,
+        None,
+    )?;
+    stream.play()?;
+
+    wait(&receiver, options.duration)
+}
+
+// Messages from the audio callbacks to the thread waiting for playback to end
+enum PlaybackEvent {
+    Finished,
+    Error(cpal::StreamError),
+}
+
+// Block until the tail has gone silent, or until the duration is up if one is given
+fn wait(
+    receiver: &std::sync::mpsc::Receiver<PlaybackEvent>,
+    duration: Option<f64>,
+) -> Result<(), anyhow::Error> {
+    // With an explicit duration, playback simply stops when it runs out
+    let deadline = duration.map(|duration| Instant::now() + Duration::from_secs_f64(duration));
+
+    loop {
+        let event = match deadline {
+            Some(deadline) => {
+                match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => return Ok(()),
+                    Err(RecvTimeoutError::Disconnected) => {
+                        bail!("Audio stream closed unexpectedly")
+                    }
+                }
+            }
+            None => receiver
+                .recv()
+                .context("Audio stream closed unexpectedly")?,
+        };
+        match event {
+            PlaybackEvent::Finished if deadline.is_none() => return Ok(()),
+            PlaybackEvent::Finished => {}
+            PlaybackEvent::Error(cpal::StreamError::DeviceNotAvailable) => {
+                bail!("Output device was disconnected during playback")
+            }
+            PlaybackEvent::Error(err) => eprintln!("an error occurred on stream: {err}"),
+        }
+    }
+}
+
+fn write_data<T>(
+    output: &mut [T],
+    channel_map: &mut ChannelMap,
+    next_sample: &mut dyn FnMut() -> (f32, f32),
+) where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = channel_map.channels();
+    for frame in output.chunks_mut(channels) {
+        let (left, right) = next_sample();
+        let mapped = channel_map.map(&[left, right]);
+
+        for (sample, &value) in frame.iter_mut().zip(mapped) {
+            *sample = T::from_sample(value);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Preset files that describe the guitar voice and the effects chain.
+
+use crate::effects::EffectsChain;
+use crate::instrument::Guitar;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A guitar voice with its effects chain, stored as TOML.
+///
+/// Every field, down to a single effect parameter, is optional in a file and falls back
+/// to the built-in default:
+///
+/// ```toml
+/// [guitar]
+/// damping = 0.998
+/// cutoff = { soft = 1200.0, hard = 5000.0 }
+/// body_response = "body.wav"
+///
+/// [[guitar.body]]
+/// frequency = 100.0
+/// q = 1.5
+/// gain = 0.2
+///
+/// [[effects]]
+/// type = "reverb"
+/// room_size = 20.0
+/// time = 2.0
+/// damping = 0.3
+/// ```
+///
+/// A `body_response` impulse response recorded from a real guitar body stands in for
+/// the `body` resonances. Relative impulse response paths start from the directory of
+/// the preset file. A preset without `[[effects]]` keeps the built-in chain, with
+/// its short limiter look-ahead when played live.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Preset {
+    pub guitar: Guitar,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effects: Option<EffectsChain>,
+}
+
+impl Preset {
+    pub fn load(path: &Path) -> Result<Preset, anyhow::Error> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read preset {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Preset::parse_in(&text, dir).with_context(|| format!("Invalid preset {}", path.display()))
+    }
+
+    /// Parse a preset whose relative impulse response paths start from the working
+    /// directory.
+    pub fn parse(text: &str) -> Result<Preset, anyhow::Error> {
+        Preset::parse_in(text, Path::new(""))
+    }
+
+    // Impulse responses are read while the preset is, so their paths are resolved first
+    fn parse_in(text: &str, dir: &Path) -> Result<Preset, anyhow::Error> {
+        let mut document: toml::Table = toml::from_str(text)?;
+        if let Some(response) = document
+            .get_mut("guitar")
+            .and_then(toml::Value::as_table_mut)
+            .and_then(|guitar| guitar.get_mut("body_response"))
+        {
+            resolve(response, dir);
+        }
+        if let Some(stages) = document
+            .get_mut("effects")
+            .and_then(toml::Value::as_array_mut)
+        {
+            for stage in stages.iter_mut().filter_map(toml::Value::as_table_mut) {
+                if let Some(response) = stage.get_mut("response") {
+                    resolve(response, dir);
+                }
+            }
+        }
+        Ok(document.try_into()?)
+    }
+
+    /// The preset as a TOML document that [`Preset::parse`] reads back unchanged.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("presets always serialize")
+    }
+}
+
+// Make a relative path in `value` start from `dir`
+fn resolve(value: &mut toml::Value, dir: &Path) {
+    if let toml::Value::String(path) = value
+        && !path.is_empty()
+        && Path::new(path).is_relative()
+    {
+        *path = dir.join(&path).to_string_lossy().into_owned();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::{Effect, Stage};
+    use crate::instrument::VelocityRange;
+
+    #[test]
+    fn missing_fields_keep_their_defaults() {
+        let preset = Preset::parse(
+            "[guitar]\ncutoff = { soft = 1000.0 }\n\n[[effects]]\ntype = \"reverb\"\nroom_size = 20\n",
+        )
+        .unwrap();
+        assert_eq!(
+            preset.guitar.cutoff,
+            VelocityRange {
+                soft: 1000.0,
+                hard: Guitar::default().cutoff.hard,
+            }
+        );
+        assert_eq!(preset.guitar.hf_damping, Guitar::default().hf_damping);
+        assert_eq!(
+            preset.effects.unwrap().stages,
+            [Stage::new(Effect::Reverb {
+                room_size: 20.0,
+                time: 0.3,
+                damping: 0.02,
+            })]
+        );
+        assert_eq!(Preset::parse("").unwrap(), Preset::default());
+    }
+
+    #[test]
+    fn impulse_responses_are_found_next_to_the_preset() {
+        let dir = std::env::temp_dir().join(format!("sound-test-preset-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut wave = fundsp::hacker::Wave::new(1, 44100.0);
+        wave.push(1.0);
+        wave.push(0.5);
+        wave.save_wav32(dir.join("body.wav")).unwrap();
+        let path = dir.join("preset.toml");
+        std::fs::write(
+            &path,
+            "[guitar]\nbody_response = \"body.wav\"\n\n\
+             [[effects]]\ntype = \"convolution\"\nresponse = \"body.wav\"\n",
+        )
+        .unwrap();
+
+        let preset = Preset::load(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let preset = preset.unwrap();
+        let body = preset.guitar.body_response.unwrap();
+        assert_eq!(body.path(), dir.join("body.wav"));
+        let Effect::Convolution { response, .. } = &preset.effects.unwrap().stages[0].effect else {
+            panic!("expected a convolution stage");
+        };
+        assert_eq!(response.path(), dir.join("body.wav"));
+    }
+
+    #[test]
+    fn invalid_presets() {
+        assert!(Preset::parse("[guitar]\ncutoff = { loud = 1.0 }").is_err());
+        assert!(Preset::parse("[[effects]]\nroom_size = 20.0").is_err());
+        assert!(Preset::parse("[[effects]]\ntype = \"reverb\"\nsize = 20.0").is_err());
+    }
+
+    #[test]
+    fn presets_round_trip_through_toml() {
+        let preset = Preset {
+            effects: Some(EffectsChain::default()),
+            ..Preset::default()
+        };
+        assert_eq!(Preset::parse(&preset.to_toml()).unwrap(), preset);
+    }
+}
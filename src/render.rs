@@ -0,0 +1,128 @@
+//! Offline rendering and WAV export.
+
+use clap::ValueEnum;
+use fundsp::hacker::*;
+use std::path::Path;
+
+// Output below -80 dB counts as silence
+const SILENCE_THRESHOLD: f32 = 1.0e-4;
+
+// How long the output must stay silent before the tail is considered over
+const SILENCE_HOLD: f64 = 0.25;
+
+// Upper bound for the tail after the last event, in case it never falls silent
+const MAX_TAIL: f64 = 30.0;
+
+/// Decides when playback is over: after the last sequenced event has ended
+/// and the effects tail has stayed below the silence threshold for a while.
+pub struct TailDetector {
+    position: u64,
+    events_end: u64,
+    hold: u64,
+    limit: u64,
+    silent_since: u64,
+}
+
+impl TailDetector {
+    pub fn new(sample_rate: f64, events_end: f64) -> Self {
+        let events_end = (events_end * sample_rate).ceil() as u64;
+        TailDetector {
+            position: 0,
+            events_end,
+            hold: (SILENCE_HOLD * sample_rate) as u64,
+            limit: events_end + (MAX_TAIL * sample_rate) as u64,
+            silent_since: 0,
+        }
+    }
+
+    /// Feed the next output frame. Returns true once the tail is over.
+    pub fn tick(&mut self, left: f32, right: f32) -> bool {
+        if left.abs().max(right.abs()) > SILENCE_THRESHOLD {
+            self.silent_since = self.position + 1;
+        }
+        self.position += 1;
+        self.is_finished()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        let start = Ord::max(self.silent_since, self.events_end);
+        self.position >= start + self.hold || self.position >= self.limit
+    }
+}
+
+/// Render the graph to a stereo wave. Without an explicit duration, rendering continues past
+/// `events_end` until the tail has gone silent; the trailing silence is trimmed off.
+pub fn render(sample_rate: f64, events_end: f64, duration: Option<f64>, net: &mut Net) -> Wave {
+    if let Some(duration) = duration {
+        return Wave::render(sample_rate, duration, net);
+    }
+
+    net.set_sample_rate(sample_rate);
+    net.allocate();
+
+    let mut detector = TailDetector::new(sample_rate, events_end);
+    let mut left = Vec::new();
+    let mut right = Vec::new();
+    let mut buffer = BufferVec::new(2);
+    let mut last_sound = 0;
+
+    while !detector.is_finished() {
+        let mut output = buffer.buffer_mut();
+        net.process(MAX_BUFFER_SIZE, &BufferRef::new(&[]), &mut output);
+        for i in 0..MAX_BUFFER_SIZE {
+            let (l, r) = (output.at_f32(0, i), output.at_f32(1, i));
+            left.push(l);
+            right.push(r);
+            if l.abs().max(r.abs()) > SILENCE_THRESHOLD {
+                last_sound = left.len();
+            }
+            if detector.tick(l, r) {
+                break;
+            }
+        }
+    }
+
+    // Keep everything up to the end of the events even if it was silent
+    let length = Ord::max(last_sound, (events_end * sample_rate).ceil() as usize);
+    left.truncate(length);
+    right.truncate(length);
+
+    let mut wave = Wave::new(0, sample_rate);
+    wave.push_channel(&left);
+    wave.push_channel(&right);
+    wave
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum BitDepth {
+    /// 16-bit integer PCM with TPDF dither.
+    #[value(name = "16")]
+    Pcm16,
+    /// 32-bit float.
+    #[default]
+    #[value(name = "32f")]
+    Float32,
+}
+
+pub fn save(wave: &mut Wave, path: &Path, bit_depth: BitDepth) -> std::io::Result<()> {
+    match bit_depth {
+        BitDepth::Pcm16 => {
+            dither_16bit(wave);
+            wave.save_wav16(path)
+        }
+        BitDepth::Float32 => wave.save_wav32(path),
+    }
+}
+
+// Add triangular (TPDF) dither of +-1 LSB ahead of the rounding done by `save_wav16`,
+// which turns the quantization error into benign noise instead of signal-correlated distortion
+fn dither_16bit(wave: &mut Wave) {
+    let lsb = 1.0 / 32767.49;
+    let length = wave.len();
+    for channel in 0..wave.channels() {
+        for (i, x) in wave.channel_mut(channel).iter_mut().enumerate() {
+            let index = (channel * length + i) as u64;
+            *x += ((rnd1(index) - rnd2(index)) * lsb) as f32;
+        }
+    }
+}
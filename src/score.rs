@@ -0,0 +1,1060 @@
+//! Scores: timed notes, built in code or read from plain-text files.
+
+use crate::articulation::{Articulation, Technique};
+use crate::chord::{Arpeggio, Chord, Strum, StrumDirection};
+use crate::fretboard::{Fretboard, STRINGS, Shape};
+use crate::tempo::{Division, TempoMap, TimeSignature};
+use anyhow::{Context, bail};
+use std::path::Path;
+
+/// A single note of a score, with times in seconds and velocity in 0...1.
+#[derive(Clone, Debug)]
+pub struct Note {
+    pub start: f64,
+    pub duration: f64,
+    pub pitch: f64,
+    pub velocity: f32,
+    /// Guitar string the note is played on, counted from the lowest. A later note on the
+    /// same string chokes this one.
+    pub string: Option<usize>,
+    pub articulations: Vec<Articulation>,
+    pub technique: Technique,
+    /// Where the string is plucked, as a fraction of its length from the bridge.
+    pub pick_position: Option<f32>,
+}
+
+/// Notes in seconds, with the tempo map they were written against. Tempo-synced
+/// effects such as delays follow the initial tempo.
+#[derive(Clone, Debug, Default)]
+pub struct Score {
+    pub notes: Vec<Note>,
+    pub tempo: TempoMap,
+}
+
+// Velocity used when a score line leaves it out
+const DEFAULT_VELOCITY: u8 = 100;
+
+// Articulation settings that score lines may leave out
+const DEFAULT_SLIDE_TIME: f64 = 0.1;
+const DEFAULT_BEND_TIME: f64 = 0.15;
+const DEFAULT_BEND_CURVE: f64 = 0.5;
+const DEFAULT_VIBRATO_RATE: f64 = 5.5;
+const DEFAULT_VIBRATO_DEPTH: f64 = 0.4;
+const DEFAULT_HARMONIC: u32 = 2;
+
+impl Score {
+    /// C major scale in quarter notes at 120 BPM, starting from C4 (MIDI note 60).
+    pub fn c_major_scale() -> Self {
+        let c_major_scale = [60.0, 62.0, 64.0, 65.0, 67.0, 69.0, 71.0, 72.0];
+
+        c_major_scale
+            .iter()
+            .fold(Score::builder().tempo(120.0), |builder, &pitch| {
+                builder.note_beats(pitch, 1.0, 1.0)
+            })
+            .build()
+    }
+
+    pub fn builder() -> ScoreBuilder {
+        ScoreBuilder {
+            time: 0.0,
+            notes: Vec::new(),
+            tempo: TempoMap::default(),
+            fretboard: Fretboard::STANDARD,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read score {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid score {}", path.display()))
+    }
+
+    /// Parse a plain-text score. Each non-empty line is either
+    ///
+    /// ```text
+    /// <pitch> <duration> [velocity]
+    /// rest <duration>
+    /// tempo <bpm>
+    /// time <beats>/<unit>
+    /// bar <number>
+    /// chord <symbol> <duration> [velocity] [down|up]
+    /// shape <frets> <duration> [velocity] [down|up]
+    /// arp <symbol> <duration> [velocity]
+    /// strum <seconds>
+    /// pattern <step> <index>...
+    /// strings <pitch> <pitch> <pitch> <pitch> <pitch> <pitch>
+    /// ```
+    ///
+    /// where pitch is a note name (C4, F#3, Bb2), optionally with a cents offset (E4-14c),
+    /// or a MIDI note number, possibly fractional, and velocity is
+    /// 0...127. A duration is either a number of seconds or a note length such as `q`,
+    /// `e.`, `1/8t` or tied lengths like `h+e` (see [`Division`]). Notes follow each other;
+    /// `tempo` and `time` take effect from the current position and `bar` moves to the
+    /// start of a bar, counting from 1. A `#` at the start of a line or after a space
+    /// starts a comment.
+    ///
+    /// `chord` strums a chord symbol such as `Am` or `G7/B` (see [`Chord`]), downwards
+    /// unless `up` is given, and `arp` plays it through the arpeggio pattern. `strum` sets
+    /// the delay between strings for the chords after it, and `pattern` the arpeggio
+    /// step length and the voicing indices it plays, counted from the lowest note.
+    /// Notes may be followed by articulations written `name:key=value,...`, with times
+    /// from the start of the note in seconds or note lengths. Those whose parameters all
+    /// have defaults may be written as a bare name, such as `vibrato`:
+    ///
+    /// ```text
+    /// hammer:to=<pitch>,at=<time>        # hammer-on
+    /// pull:to=<pitch>,at=<time>          # pull-off
+    /// slide:to=<pitch>,at=<time>,time=<time>
+    /// bend:by=<semitones>,at=<time>,time=<time>,curve=<exponent>
+    /// vibrato:delay=<time>,rate=<hz>,depth=<semitones>
+    /// palm                               # palm mute
+    /// harmonic:n=<multiple>              # natural harmonic, n=2 by default
+    /// artificial:n=<multiple>            # artificial harmonic
+    /// pick:at=<fraction>                 # pick position from the bridge, 0...1
+    /// ```
+    ///
+    /// For example `A3 h hammer:to=B3,at=e pull:to=A3,at=q`, `E4 h bend:by=2,time=e` or
+    /// `E2 e palm pick:at=0.1`.
+    /// `shape` strums frets on six strings such as `x32010` (see [`Shape`]), in standard
+    /// tuning unless `strings` gives other open-string pitches. A note on a string
+    /// chokes whatever that string was still playing.
+    ///
+    /// The score starts at 120 BPM in 4/4.
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let mut builder = Score::builder();
+        let mut strum = Strum::default();
+        let mut arpeggio = Arpeggio::default();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            // Articulation times count from the start of the note, which is the current position
+            let seconds = |length| builder.length_seconds(length);
+            let parsed =
+                parse_line(&fields, &seconds).with_context(|| format!("line {}", index + 1))?;
+            builder = match parsed {
+                Line::Note {
+                    pitch,
+                    duration,
+                    velocity,
+                    playing,
+                } => {
+                    let duration = builder.length_seconds(duration);
+                    let mut builder = playing
+                        .articulations
+                        .into_iter()
+                        .fold(
+                            builder.note(pitch, duration, velocity),
+                            ScoreBuilder::articulation,
+                        )
+                        .technique(playing.technique);
+                    if let Some(position) = playing.pick_position {
+                        builder = builder.pick_position(position);
+                    }
+                    builder
+                }
+                Line::Rest(Length::Seconds(duration)) => builder.rest(duration),
+                Line::Rest(Length::Beats(beats)) => builder.rest_beats(beats),
+                Line::Tempo(bpm) => builder.tempo(bpm),
+                Line::Time(signature) => builder.time_signature(signature),
+                Line::Bar(bar) => builder.at_bar(bar),
+                Line::Chord {
+                    chord,
+                    duration,
+                    velocity,
+                    direction,
+                } => {
+                    let duration = builder.length_seconds(duration);
+                    let strum = Strum {
+                        direction: direction.unwrap_or_default(),
+                        ..strum
+                    };
+                    builder.strum(&chord, duration, velocity, &strum)
+                }
+                Line::Shape {
+                    shape,
+                    duration,
+                    velocity,
+                    direction,
+                } => {
+                    let duration = builder.length_seconds(duration);
+                    let strum = Strum {
+                        direction: direction.unwrap_or_default(),
+                        ..strum
+                    };
+                    builder.strum_shape(&shape, duration, velocity, &strum)
+                }
+                Line::Strings(fretboard) => builder.fretboard(fretboard),
+                Line::Arpeggio {
+                    chord,
+                    duration,
+                    velocity,
+                } => {
+                    let duration = builder.length_seconds(duration);
+                    builder.arpeggio(&chord, duration, velocity, &arpeggio)
+                }
+                Line::Strum(delay) => {
+                    strum.delay = delay;
+                    builder
+                }
+                Line::Pattern(pattern) => {
+                    arpeggio = pattern;
+                    builder
+                }
+            };
+        }
+
+        Ok(builder.build())
+    }
+}
+
+/// Builds a score note by note. Each note or rest starts where the previous one ended.
+/// Positions and durations are in seconds or in beats of the tempo map built along the way.
+pub struct ScoreBuilder {
+    time: f64,
+    notes: Vec<Note>,
+    tempo: TempoMap,
+    fretboard: Fretboard,
+}
+
+impl ScoreBuilder {
+    /// Append a note; pitch is a MIDI note number and velocity is in 0...1.
+    pub fn note(mut self, pitch: f64, duration: f64, velocity: f32) -> Self {
+        self.notes.push(Note {
+            start: self.time,
+            duration,
+            pitch,
+            velocity,
+            string: None,
+            articulations: Vec::new(),
+            technique: Technique::Open,
+            pick_position: None,
+        });
+        self.time += duration;
+        self
+    }
+
+    /// Add an articulation to the last note, timed from its start.
+    pub fn articulation(mut self, articulation: Articulation) -> Self {
+        if let Some(note) = self.notes.last_mut() {
+            note.articulations.push(articulation);
+        }
+        self
+    }
+
+    /// Play the last note with a technique such as palm muting.
+    pub fn technique(mut self, technique: Technique) -> Self {
+        if let Some(note) = self.notes.last_mut() {
+            note.technique = technique;
+        }
+        self
+    }
+
+    /// Pluck the last note at a fraction of the string length from the bridge.
+    pub fn pick_position(mut self, position: f32) -> Self {
+        if let Some(note) = self.notes.last_mut() {
+            note.pick_position = Some(position);
+        }
+        self
+    }
+
+    pub fn rest(mut self, duration: f64) -> Self {
+        self.time += duration;
+        self
+    }
+
+    /// Move the position of the next note, e.g. back in time to layer a chord.
+    pub fn at(mut self, time: f64) -> Self {
+        self.time = time;
+        self
+    }
+
+    /// Append a note that lasts `beats` quarter notes at the tempo where it starts.
+    pub fn note_beats(self, pitch: f64, beats: f64, velocity: f32) -> Self {
+        let duration = self.beats_to_seconds(beats);
+        self.note(pitch, duration, velocity)
+    }
+
+    pub fn rest_beats(self, beats: f64) -> Self {
+        let duration = self.beats_to_seconds(beats);
+        self.rest(duration)
+    }
+
+    /// Move to the start of a bar, counting from 1.
+    pub fn at_bar(mut self, bar: u32) -> Self {
+        self.time = self.tempo.seconds(self.tempo.bar_start(bar));
+        self
+    }
+
+    /// Change the tempo in BPM from the current position on.
+    pub fn tempo(mut self, bpm: f64) -> Self {
+        let beat = self.tempo.beats(self.time);
+        self.tempo.set_tempo(beat, bpm);
+        self
+    }
+
+    /// Change the time signature from the current position on, which should start a bar.
+    pub fn time_signature(mut self, signature: TimeSignature) -> Self {
+        let beat = self.tempo.beats(self.time);
+        self.tempo.set_time_signature(beat, signature);
+        self
+    }
+
+    /// Strum the voicing of a chord, letting every string ring until the chord ends.
+    /// The voicing is played on the strings from the lowest, one note per string.
+    pub fn strum(self, chord: &Chord, duration: f64, velocity: f32, strum: &Strum) -> Self {
+        let notes: Vec<(usize, u8)> = chord.voicing().into_iter().enumerate().collect();
+        let strings = notes.len();
+        self.strum_strings(&notes, strings, duration, velocity, strum)
+    }
+
+    /// Strum a chord shape on the current fretboard, one pluck per string that is not
+    /// muted. Muted strings still take their share of the strum time.
+    pub fn strum_shape(self, shape: &Shape, duration: f64, velocity: f32, strum: &Strum) -> Self {
+        let notes = self.fretboard.pitches(shape);
+        self.strum_strings(&notes, STRINGS, duration, velocity, strum)
+    }
+
+    /// Use different open strings for the chord shapes after this point.
+    pub fn fretboard(mut self, fretboard: Fretboard) -> Self {
+        self.fretboard = fretboard;
+        self
+    }
+
+    // Pluck each (string, pitch) with the pick crossing `strings` strings
+    fn strum_strings(
+        mut self,
+        notes: &[(usize, u8)],
+        strings: usize,
+        duration: f64,
+        velocity: f32,
+        strum: &Strum,
+    ) -> Self {
+        let offsets = strum.offsets(strings);
+        for &(string, pitch) in notes {
+            let offset = offsets[string];
+            self.notes.push(Note {
+                start: self.time + offset,
+                duration: (duration - offset).max(0.0),
+                pitch: pitch as f64,
+                velocity,
+                string: Some(string),
+                articulations: Vec::new(),
+                technique: Technique::Open,
+                pick_position: None,
+            });
+        }
+        self.time += duration;
+        self
+    }
+
+    /// Play the voicing of a chord one note per step of the arpeggio pattern, repeating
+    /// the pattern until the chord ends. Steps follow the tempo map, and every note rings
+    /// until the chord ends.
+    pub fn arpeggio(
+        mut self,
+        chord: &Chord,
+        duration: f64,
+        velocity: f32,
+        arpeggio: &Arpeggio,
+    ) -> Self {
+        let notes = chord.voicing();
+        let end = self.time + duration;
+        let first_beat = self.tempo.beats(self.time);
+        if !arpeggio.pattern.is_empty() && arpeggio.step > 0.0 {
+            for (step, index) in arpeggio.pattern.iter().cycle().enumerate() {
+                let start = self.tempo.seconds(first_beat + step as f64 * arpeggio.step);
+                if start >= end {
+                    break;
+                }
+                let string = index % notes.len();
+                self.notes.push(Note {
+                    start,
+                    duration: end - start,
+                    pitch: notes[string] as f64,
+                    velocity,
+                    string: Some(string),
+                    articulations: Vec::new(),
+                    technique: Technique::Open,
+                    pick_position: None,
+                });
+            }
+        }
+        self.time = end;
+        self
+    }
+
+    // Seconds from the current position to `beats` beats later
+    fn beats_to_seconds(&self, beats: f64) -> f64 {
+        let start = self.tempo.beats(self.time);
+        self.tempo.seconds(start + beats) - self.time
+    }
+
+    fn length_seconds(&self, length: Length) -> f64 {
+        match length {
+            Length::Seconds(seconds) => seconds,
+            Length::Beats(beats) => self.beats_to_seconds(beats),
+        }
+    }
+
+    pub fn build(self) -> Score {
+        Score {
+            notes: self.notes,
+            tempo: self.tempo,
+        }
+    }
+}
+
+enum Line {
+    Note {
+        pitch: f64,
+        duration: Length,
+        velocity: f32,
+        playing: Playing,
+    },
+    Rest(Length),
+    Tempo(f64),
+    Time(TimeSignature),
+    Bar(u32),
+    Chord {
+        chord: Chord,
+        duration: Length,
+        velocity: f32,
+        direction: Option<StrumDirection>,
+    },
+    Shape {
+        shape: Shape,
+        duration: Length,
+        velocity: f32,
+        direction: Option<StrumDirection>,
+    },
+    Strings(Fretboard),
+    Arpeggio {
+        chord: Chord,
+        duration: Length,
+        velocity: f32,
+    },
+    Strum(f64),
+    Pattern(Arpeggio),
+}
+
+enum Length {
+    Seconds(f64),
+    Beats(f64),
+}
+
+fn parse_line(fields: &[&str], seconds: &dyn Fn(Length) -> f64) -> Result<Line, anyhow::Error> {
+    let (first, rest) = fields.split_first().context("empty line")?;
+
+    if first.eq_ignore_ascii_case("rest") {
+        let [duration] = rest else {
+            bail!("expected `rest <duration>`");
+        };
+        return Ok(Line::Rest(parse_duration(duration)?));
+    }
+
+    if first.eq_ignore_ascii_case("tempo") {
+        let [bpm] = rest else {
+            bail!("expected `tempo <bpm>`");
+        };
+        return Ok(Line::Tempo(parse_tempo(bpm)?));
+    }
+
+    if first.eq_ignore_ascii_case("time") {
+        let [signature] = rest else {
+            bail!("expected `time <beats>/<unit>`");
+        };
+        return Ok(Line::Time(TimeSignature::parse(signature)?));
+    }
+
+    if first.eq_ignore_ascii_case("bar") {
+        let [bar] = rest else {
+            bail!("expected `bar <number>`");
+        };
+        let bar = bar
+            .parse::<u32>()
+            .ok()
+            .filter(|&bar| bar > 0)
+            .with_context(|| format!("invalid bar '{bar}'"))?;
+        return Ok(Line::Bar(bar));
+    }
+
+    if first.eq_ignore_ascii_case("chord") {
+        let (symbol, duration, velocity, direction) = parse_strummed(rest)
+            .context("expected `chord <symbol> <duration> [velocity] [down|up]`")?;
+        return Ok(Line::Chord {
+            chord: Chord::parse(symbol)?,
+            duration,
+            velocity,
+            direction,
+        });
+    }
+
+    if first.eq_ignore_ascii_case("shape") {
+        let (frets, duration, velocity, direction) = parse_strummed(rest)
+            .context("expected `shape <frets> <duration> [velocity] [down|up]`")?;
+        return Ok(Line::Shape {
+            shape: Shape::parse(frets)?,
+            duration,
+            velocity,
+            direction,
+        });
+    }
+
+    if first.eq_ignore_ascii_case("strings") {
+        return Ok(Line::Strings(Fretboard::parse(&rest.join(" "))?));
+    }
+
+    if first.eq_ignore_ascii_case("arp") {
+        let (symbol, duration, velocity) = match rest {
+            [symbol, duration] => (symbol, duration, None),
+            [symbol, duration, velocity] => (symbol, duration, Some(velocity)),
+            _ => bail!("expected `arp <symbol> <duration> [velocity]`"),
+        };
+        return Ok(Line::Arpeggio {
+            chord: Chord::parse(symbol)?,
+            duration: parse_duration(duration)?,
+            velocity: parse_velocity(velocity.copied())?,
+        });
+    }
+
+    if first.eq_ignore_ascii_case("strum") {
+        let [delay] = rest else {
+            bail!("expected `strum <seconds>`");
+        };
+        return match delay.parse::<f64>() {
+            Ok(delay) if delay.is_finite() && delay >= 0.0 => Ok(Line::Strum(delay)),
+            _ => bail!("invalid strum delay '{delay}'"),
+        };
+    }
+
+    if first.eq_ignore_ascii_case("pattern") {
+        let [step, indices @ ..] = rest else {
+            bail!("expected `pattern <step> <index>...`");
+        };
+        if indices.is_empty() {
+            bail!("expected `pattern <step> <index>...`");
+        }
+        let pattern = indices
+            .iter()
+            .map(|i| {
+                i.parse::<usize>()
+                    .with_context(|| format!("invalid pattern index '{i}'"))
+            })
+            .collect::<Result<_, _>>()?;
+        return Ok(Line::Pattern(Arpeggio {
+            pattern,
+            step: Division::parse(step)?.beats(),
+        }));
+    }
+
+    // Articulations are the fields written as `name:key=value,...`, or a bare name
+    let (articulations, rest): (Vec<&str>, Vec<&str>) = rest.iter().partition(|field| {
+        field.contains(':') || BARE_ARTICULATIONS.contains(&field.to_lowercase().as_str())
+    });
+    let (duration, velocity) = match rest[..] {
+        [duration] => (duration, None),
+        [duration, velocity] => (duration, Some(velocity)),
+        _ => bail!("expected `<pitch> <duration> [velocity] [articulation...]`"),
+    };
+    let pitch = parse_pitch(first)?;
+
+    Ok(Line::Note {
+        pitch,
+        duration: parse_duration(duration)?,
+        velocity: parse_velocity(velocity)?,
+        playing: parse_playing(pitch, &articulations, seconds)?,
+    })
+}
+
+// Articulations that need no parameters, so they may be written without a colon
+const BARE_ARTICULATIONS: [&str; 4] = ["vibrato", "palm", "harmonic", "artificial"];
+
+// How a note line asks for its note to be played
+#[derive(Default)]
+struct Playing {
+    articulations: Vec<Articulation>,
+    technique: Technique,
+    pick_position: Option<f32>,
+}
+
+// Articulations such as `hammer:to=D4,at=e` or `bend:by=2,at=0.1,time=e`, techniques
+// such as `palm` and the pick position. Target pitches are turned into changes from the
+// pitch the articulations before reached.
+fn parse_playing(
+    pitch: f64,
+    fields: &[&str],
+    seconds: &dyn Fn(Length) -> f64,
+) -> Result<Playing, anyhow::Error> {
+    let mut current = pitch;
+    let mut playing = Playing::default();
+    for field in fields {
+        let (name, parameters) = field.split_once(':').unwrap_or((field, ""));
+        let name = name.to_lowercase();
+        let parameters = ArticulationParameters::parse(parameters)
+            .with_context(|| format!("invalid articulation '{field}'"))?;
+        let known: &[&str] = match name.as_str() {
+            "hammer" | "pull" => &["to", "at"],
+            "slide" => &["to", "at", "time"],
+            "bend" => &["by", "at", "time", "curve"],
+            "vibrato" => &["delay", "rate", "depth"],
+            "palm" => &[],
+            "harmonic" | "artificial" => &["n"],
+            "pick" => &["at"],
+            _ => bail!(
+                "unknown articulation '{name}'; expected hammer, pull, slide, bend, vibrato, \
+                 palm, harmonic, artificial or pick"
+            ),
+        };
+        if let Some(key) = parameters.unknown(known) {
+            bail!("unknown parameter '{key}' in '{field}'");
+        }
+
+        let time = |key: &str, default: f64| -> Result<f64, anyhow::Error> {
+            parameters
+                .get(key)
+                .map_or(Ok(default), |value| Ok(seconds(parse_duration(value)?)))
+        };
+        let number = |key: &str, default: Option<f64>| -> Result<f64, anyhow::Error> {
+            match parameters.get(key) {
+                Some(value) => match value.parse::<f64>() {
+                    Ok(number) if number.is_finite() => Ok(number),
+                    _ => bail!("invalid {key} '{value}' in '{field}'"),
+                },
+                None => default.with_context(|| format!("missing {key} in '{field}'")),
+            }
+        };
+        let multiple = || -> Result<u32, anyhow::Error> {
+            match parameters.get("n") {
+                Some(value) => value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&n| n >= 2)
+                    .with_context(|| format!("invalid n '{value}' in '{field}'")),
+                None => Ok(DEFAULT_HARMONIC),
+            }
+        };
+        let mut target = || -> Result<f64, anyhow::Error> {
+            let to = parameters
+                .get("to")
+                .with_context(|| format!("missing to in '{field}'"))?;
+            let to = parse_pitch(to)?;
+            let semitones = to - current;
+            current = to;
+            Ok(semitones)
+        };
+
+        let articulation = match name.as_str() {
+            "hammer" => Articulation::HammerOn {
+                semitones: target()?,
+                time: time("at", 0.0)?,
+            },
+            "pull" => Articulation::PullOff {
+                semitones: target()?,
+                time: time("at", 0.0)?,
+            },
+            "slide" => Articulation::Slide {
+                semitones: target()?,
+                time: time("at", 0.0)?,
+                length: time("time", DEFAULT_SLIDE_TIME)?,
+            },
+            "bend" => {
+                let semitones = number("by", None)?;
+                current += semitones;
+                Articulation::Bend {
+                    semitones,
+                    time: time("at", 0.0)?,
+                    length: time("time", DEFAULT_BEND_TIME)?,
+                    curve: number("curve", Some(DEFAULT_BEND_CURVE))?,
+                }
+            }
+            "vibrato" => Articulation::Vibrato {
+                delay: time("delay", 0.0)?,
+                rate: number("rate", Some(DEFAULT_VIBRATO_RATE))?,
+                depth: number("depth", Some(DEFAULT_VIBRATO_DEPTH))?,
+            },
+            _ => {
+                match name.as_str() {
+                    "palm" => playing.technique = Technique::PalmMute,
+                    "harmonic" => {
+                        playing.technique = Technique::NaturalHarmonic {
+                            multiple: multiple()?,
+                        }
+                    }
+                    "artificial" => {
+                        playing.technique = Technique::ArtificialHarmonic {
+                            multiple: multiple()?,
+                        }
+                    }
+                    _ => {
+                        let position = number("at", None)?;
+                        if !(0.0..=1.0).contains(&position) {
+                            bail!("pick position {position} is outside 0...1 in '{field}'");
+                        }
+                        playing.pick_position = Some(position as f32);
+                    }
+                }
+                continue;
+            }
+        };
+        playing.articulations.push(articulation);
+    }
+    Ok(playing)
+}
+
+// The `key=value` pairs of an articulation
+struct ArticulationParameters<'a>(Vec<(&'a str, &'a str)>);
+
+impl<'a> ArticulationParameters<'a> {
+    fn parse(s: &'a str) -> Result<Self, anyhow::Error> {
+        let pairs = s
+            .split(',')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| pair.split_once('=').context("expected key=value"))
+            .collect::<Result<_, _>>()?;
+        Ok(ArticulationParameters(pairs))
+    }
+
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.0.iter().find(|(k, _)| *k == key).map(|&(_, v)| v)
+    }
+
+    // The first key that is not one of `known`
+    fn unknown(&self, known: &[&str]) -> Option<&'a str> {
+        self.0
+            .iter()
+            .map(|&(key, _)| key)
+            .find(|key| !known.contains(key))
+    }
+}
+
+// The arguments of a strummed chord: what to play, then the duration, an optional
+// velocity and an optional direction
+fn parse_strummed<'a>(
+    fields: &[&'a str],
+) -> Result<(&'a str, Length, f32, Option<StrumDirection>), anyhow::Error> {
+    let (chord, duration, velocity, direction) = match *fields {
+        [chord, duration] => (chord, duration, None, None),
+        [chord, duration, last] if last.parse::<u8>().is_err() => {
+            (chord, duration, None, Some(last))
+        }
+        [chord, duration, velocity] => (chord, duration, Some(velocity), None),
+        [chord, duration, velocity, direction] => {
+            (chord, duration, Some(velocity), Some(direction))
+        }
+        _ => bail!("wrong number of fields"),
+    };
+    let direction = match direction.map(|d| d.to_ascii_lowercase()) {
+        Some(d) if d == "down" => Some(StrumDirection::Down),
+        Some(d) if d == "up" => Some(StrumDirection::Up),
+        Some(d) => bail!("invalid strum direction '{d}'; expected down or up"),
+        None => None,
+    };
+    Ok((
+        chord,
+        parse_duration(duration)?,
+        parse_velocity(velocity)?,
+        direction,
+    ))
+}
+
+// MIDI velocity 0...127, scaled to 0...1
+fn parse_velocity(s: Option<&str>) -> Result<f32, anyhow::Error> {
+    let velocity = match s {
+        Some(v) => v
+            .parse::<u8>()
+            .ok()
+            .filter(|&v| v <= 127)
+            .with_context(|| format!("invalid velocity '{v}'"))?,
+        None => DEFAULT_VELOCITY,
+    };
+    Ok(velocity as f32 / 127.0)
+}
+
+// Comments start with a `#` at the start of a line or after whitespace, so sharps as in
+// F#3 are left alone
+fn strip_comment(line: &str) -> &str {
+    let mut previous = ' ';
+    for (index, c) in line.char_indices() {
+        if c == '#' && previous.is_whitespace() {
+            return &line[..index];
+        }
+        previous = c;
+    }
+    line
+}
+
+fn parse_tempo(s: &str) -> Result<f64, anyhow::Error> {
+    match s.parse::<f64>() {
+        Ok(bpm) if bpm.is_finite() && bpm > 0.0 => Ok(bpm),
+        _ => bail!("invalid tempo '{s}'"),
+    }
+}
+
+// Seconds, or note lengths tied with `+`
+fn parse_duration(s: &str) -> Result<Length, anyhow::Error> {
+    match s.parse::<f64>() {
+        Ok(d) if d.is_finite() && d >= 0.0 => Ok(Length::Seconds(d)),
+        Ok(_) => bail!("invalid duration '{s}'"),
+        Err(_) => {
+            let mut beats = 0.0;
+            for length in s.split('+') {
+                beats += Division::parse(length)?.beats();
+            }
+            Ok(Length::Beats(beats))
+        }
+    }
+}
+
+/// Parse a MIDI note number or a note name with octave, where C4 is MIDI note 60.
+/// Fractional note numbers and a cents offset such as `E4-14c` or `C4+50c` give
+/// microtonal pitches.
+pub fn parse_pitch(s: &str) -> Result<f64, anyhow::Error> {
+    if let Some(body) = s.strip_suffix('c')
+        && let Some(split) = body.rfind(['+', '-'])
+        && split > 0
+    {
+        let (note, cents) = body.split_at(split);
+        let cents: f64 = cents
+            .parse()
+            .ok()
+            .filter(|cents: &f64| cents.is_finite())
+            .with_context(|| format!("invalid cents in pitch '{s}'"))?;
+        return Ok(parse_pitch(note)? + cents / 100.0);
+    }
+
+    if let Ok(number) = s.parse::<f64>() {
+        if !(0.0..=127.0).contains(&number) {
+            bail!("MIDI note {s} is out of range");
+        }
+        return Ok(number);
+    }
+
+    let (semitone, octave) = parse_note_name(s).with_context(|| format!("invalid pitch '{s}'"))?;
+    let octave: i32 = octave
+        .parse()
+        .with_context(|| format!("invalid pitch '{s}'"))?;
+
+    let number = (octave + 1) * 12 + semitone;
+    if !(0..=127).contains(&number) {
+        bail!("pitch {s} is out of range");
+    }
+    Ok(number as f64)
+}
+
+/// Parse a note letter in either case with any `#` and `b` accidentals after it, such as
+/// `F#` or `bb`, and return its semitones above C and the rest of `s`. Flats below C
+/// come out negative.
+pub(crate) fn parse_note_name(s: &str) -> Result<(i32, &str), anyhow::Error> {
+    let mut chars = s.chars();
+    let semitone = match chars.next().map(|c| c.to_ascii_uppercase()) {
+        Some('C') => 0,
+        Some('D') => 2,
+        Some('E') => 4,
+        Some('F') => 5,
+        Some('G') => 7,
+        Some('A') => 9,
+        Some('B') => 11,
+        _ => bail!("expected a note letter A to G"),
+    };
+    let rest = chars.as_str();
+    let suffix = rest.trim_start_matches(['#', 'b']);
+    let shift: i32 = rest[..rest.len() - suffix.len()]
+        .chars()
+        .map(|c| if c == '#' { 1 } else { -1 })
+        .sum();
+    Ok((semitone + shift, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::articulation::pitch_offset;
+
+    fn parse(text: &str) -> Score {
+        Score::parse(text).expect("valid score")
+    }
+
+    #[test]
+    fn notes_follow_each_other() {
+        let score = parse("C4 0.5 127\n60 0.25\nrest 0.5\nF#3 1 0\n");
+        let notes: Vec<(f64, f64, f64, f32)> = score
+            .notes
+            .iter()
+            .map(|note| (note.start, note.duration, note.pitch, note.velocity))
+            .collect();
+        assert_eq!(
+            notes,
+            [
+                (0.0, 0.5, 60.0, 1.0),
+                (0.5, 0.25, 60.0, DEFAULT_VELOCITY as f32 / 127.0),
+                (1.25, 1.0, 54.0, 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let score = parse("# intro\n\nC#4 1 # sharp, not a comment\n   \n");
+        assert_eq!(score.notes.len(), 1);
+        assert_eq!(score.notes[0].pitch, 61.0);
+    }
+
+    #[test]
+    fn pitches() {
+        assert_eq!(parse_pitch("C4").unwrap(), 60.0);
+        assert_eq!(parse_pitch("Bb2").unwrap(), 46.0);
+        assert_eq!(parse_pitch("a0").unwrap(), 21.0);
+        assert_eq!(parse_pitch("69").unwrap(), 69.0);
+        assert!(parse_pitch("H4").is_err());
+        assert!(parse_pitch("C10").is_err());
+        assert!(parse_pitch("128").is_err());
+    }
+
+    fn timing(score: &Score) -> Vec<(f64, f64)> {
+        score
+            .notes
+            .iter()
+            .map(|note| (note.start, note.duration))
+            .collect()
+    }
+
+    #[test]
+    fn note_lengths_at_the_default_tempo() {
+        let score = parse("C4 q\nC4 e.\nC4 1/8t\nC4 h+e\nC4 w");
+        let durations: Vec<f64> = score.notes.iter().map(|note| note.duration).collect();
+        let expected = [0.5, 0.375, 1.0 / 6.0, 1.25, 2.0];
+        for (duration, expected) in durations.iter().zip(expected) {
+            assert!((duration - expected).abs() < 1e-9, "{durations:?}");
+        }
+        assert!(Score::parse("C4 q+x").is_err());
+    }
+
+    #[test]
+    fn tempo_changes_from_the_current_position() {
+        let score = parse("C4 q\ntempo 60\nC4 q\nrest e\nC4 0.25");
+        assert_eq!(timing(&score), [(0.0, 0.5), (0.5, 1.0), (2.0, 0.25)]);
+        assert_eq!(score.tempo.initial_tempo(), 120.0);
+        assert!(Score::parse("tempo 0").is_err());
+    }
+
+    #[test]
+    fn bars_follow_the_time_signature() {
+        let score = parse("time 3/4\nbar 3\nC4 q\ntime 6/8\nbar 5\nC4 q");
+        // Two bars of 3/4 are six beats, and two more make twelve
+        assert_eq!(timing(&score), [(3.0, 0.5), (6.0, 0.5)]);
+        assert!(Score::parse("time 3/5").is_err());
+        assert!(Score::parse("bar 0").is_err());
+    }
+
+    #[test]
+    fn chord_lines_strum_the_voicing() {
+        let score = parse("strum 0.01\nchord Am q 127 up");
+        let notes: Vec<(f64, f64, Option<usize>)> = score
+            .notes
+            .iter()
+            .map(|note| (note.start, note.pitch, note.string))
+            .collect();
+        let pitches = [45.0, 48.0, 52.0, 57.0, 60.0, 64.0];
+        for (string, &(start, pitch, on)) in notes.iter().enumerate() {
+            assert!((start - (5 - string) as f64 * 0.01).abs() < 1e-9);
+            assert_eq!((pitch, on), (pitches[string], Some(string)));
+        }
+        assert_eq!(score.notes.len(), 6);
+        assert!(Score::parse("chord Am q sideways").is_err());
+        assert_eq!(parse("chord am q").notes.len(), 6);
+    }
+
+    #[test]
+    fn arpeggios_step_through_the_pattern() {
+        let score = parse("pattern e 0 5\narp C q");
+        let notes: Vec<(f64, f64, f64)> = score
+            .notes
+            .iter()
+            .map(|note| (note.start, note.duration, note.pitch))
+            .collect();
+        assert_eq!(notes, [(0.0, 0.5, 48.0), (0.25, 0.25, 67.0)]);
+        assert!(Score::parse("pattern e").is_err());
+    }
+
+    #[test]
+    fn shape_lines_play_each_fretted_string() {
+        let score = parse("strings D2 A2 D3 G3 B3 E4\nshape 0-0-0-x-x-x q\nshape xx0232 q");
+        let notes: Vec<(f64, Option<usize>)> = score
+            .notes
+            .iter()
+            .map(|note| (note.pitch, note.string))
+            .collect();
+        assert_eq!(
+            notes,
+            [
+                (38.0, Some(0)),
+                (45.0, Some(1)),
+                (50.0, Some(2)),
+                (50.0, Some(2)),
+                (57.0, Some(3)),
+                (62.0, Some(4)),
+                (66.0, Some(5)),
+            ]
+        );
+        assert!(Score::parse("shape x3201 q").is_err());
+    }
+
+    #[test]
+    fn hammer_ons_and_pull_offs_follow_the_target_pitches() {
+        let score = parse("A3 h hammer:to=B3,at=e pull:to=A3,at=q");
+        let articulations = &score.notes[0].articulations;
+        assert_eq!(
+            articulations,
+            &[
+                Articulation::HammerOn {
+                    time: 0.25,
+                    semitones: 2.0,
+                },
+                Articulation::PullOff {
+                    time: 0.5,
+                    semitones: -2.0,
+                },
+            ]
+        );
+        let offsets = [0.1, 0.3, 0.6].map(|t| pitch_offset(articulations, t));
+        assert_eq!(offsets, [0.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn bare_articulations() {
+        let score = parse("E4 q vibrato palm");
+        assert_eq!(
+            score.notes[0].articulations,
+            [Articulation::Vibrato {
+                delay: 0.0,
+                rate: DEFAULT_VIBRATO_RATE,
+                depth: DEFAULT_VIBRATO_DEPTH,
+            }]
+        );
+        assert_eq!(score.notes[0].technique, Technique::PalmMute);
+        assert!(Score::parse("E4 q hammer").is_err());
+        assert!(Score::parse("E4 q wah").is_err());
+    }
+
+    #[test]
+    fn slides_step_a_whole_fret_at_a_time() {
+        let score = parse("E3 h slide:to=G3,at=0.1,time=0.3");
+        let articulations = &score.notes[0].articulations;
+        let offsets = [0.0, 0.15, 0.25, 0.35, 0.5].map(|t| pitch_offset(articulations, t));
+        assert_eq!(offsets, [0.0, 0.0, 1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = Score::parse("C4 1\n\nC4 1 200\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 3");
+        assert_eq!(format!("{error:#}"), "line 3: invalid velocity '200'");
+        let error = Score::parse("rest\n").unwrap_err();
+        assert_eq!(format!("{error:#}"), "line 1: expected `rest <duration>`");
+    }
+}
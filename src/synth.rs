@@ -0,0 +1,267 @@
+//! Putting it together: a score played by an instrument through an effects chain.
+
+use crate::effects::EffectsChain;
+use crate::instrument::{Guitar, Instrument, NoteParams};
+use crate::render;
+use crate::score::Score;
+use crate::tuning::Tuning;
+use fundsp::hacker::*;
+use std::collections::HashMap;
+
+// How quickly a choked string falls silent
+const CHOKE_FADE: f64 = 0.02;
+
+/// A score, the instrument that plays it in a tuning and the master effects chain.
+///
+/// ```no_run
+/// use sound_test::{EffectsChain, Guitar, Score, Synth};
+///
+/// let synth = Synth::builder()
+///     .instrument(Guitar::default())
+///     .score(Score::builder().note(64.0, 0.5, 0.8).rest(0.25).note(67.0, 1.0, 1.0).build())
+///     .effects(EffectsChain::empty().reverb(10.0, 1.5, 0.5))
+///     .build();
+/// let wave = synth.render(48000.0, None);
+/// ```
+pub struct Synth {
+    score: Score,
+    instrument: Box<dyn Instrument>,
+    tuning: Tuning,
+    effects: EffectsChain,
+}
+
+pub struct SynthBuilder {
+    score: Score,
+    instrument: Box<dyn Instrument>,
+    tuning: Tuning,
+    effects: EffectsChain,
+}
+
+impl Synth {
+    /// Start from the C major scale on the guitar in equal temperament with the default
+    /// effects.
+    pub fn builder() -> SynthBuilder {
+        SynthBuilder {
+            score: Score::c_major_scale(),
+            instrument: Box::new(Guitar::default()),
+            tuning: Tuning::default(),
+            effects: EffectsChain::default(),
+        }
+    }
+
+    pub fn score(&self) -> &Score {
+        &self.score
+    }
+
+    pub fn instrument(&self) -> &dyn Instrument {
+        self.instrument.as_ref()
+    }
+
+    pub fn tuning(&self) -> &Tuning {
+        &self.tuning
+    }
+
+    pub fn effects(&self) -> &EffectsChain {
+        &self.effects
+    }
+
+    /// Build a fresh stereo graph that plays the score.
+    pub fn net(&self) -> Net {
+        create_audio_graph(
+            &self.score,
+            self.instrument.as_ref(),
+            &self.tuning,
+            &self.effects,
+        )
+    }
+
+    /// End time in seconds of the last sequenced event, including the instrument release.
+    pub fn events_end(&self) -> f64 {
+        let release = self.instrument.release();
+        self.score
+            .notes
+            .iter()
+            .map(|note| note.start + note.duration + release)
+            .fold(0.0, f64::max)
+    }
+
+    /// Render the score offline. Without a duration, rendering stops once the tail is silent.
+    pub fn render(&self, sample_rate: f64, duration: Option<f64>) -> Wave {
+        render::render(sample_rate, self.events_end(), duration, &mut self.net())
+    }
+}
+
+impl SynthBuilder {
+    pub fn score(mut self, score: Score) -> Self {
+        self.score = score;
+        self
+    }
+
+    pub fn instrument(mut self, instrument: impl Instrument + 'static) -> Self {
+        self.instrument = Box::new(instrument);
+        self
+    }
+
+    pub fn boxed_instrument(mut self, instrument: Box<dyn Instrument>) -> Self {
+        self.instrument = instrument;
+        self
+    }
+
+    pub fn tuning(mut self, tuning: Tuning) -> Self {
+        self.tuning = tuning;
+        self
+    }
+
+    pub fn effects(mut self, effects: EffectsChain) -> Self {
+        self.effects = effects;
+        self
+    }
+
+    pub fn build(self) -> Synth {
+        Synth {
+            score: self.score,
+            instrument: self.instrument,
+            tuning: self.tuning,
+            effects: self.effects,
+        }
+    }
+}
+
+/// Sequence every note of the score on the instrument, at the frequencies of the tuning,
+/// and run the mix through the effects. Notes on keys the tuning leaves unused are skipped.
+pub fn create_audio_graph(
+    score: &Score,
+    instrument: &dyn Instrument,
+    tuning: &Tuning,
+    effects: &EffectsChain,
+) -> Net {
+    // Use Net for dynamic sequencing
+    let mut net = Net::new(0, 2);
+
+    // Create a sequencer to play notes one by one
+    let mut sequencer = Sequencer::new(false, 2);
+
+    let chokes = choke_times(score);
+
+    // Add each note to the sequencer with proper timing
+    for (note, choke) in score.notes.iter().zip(chokes) {
+        let Some(frequency) = tuning.frequency(note.pitch) else {
+            continue;
+        };
+        let start_time = note.start;
+        let mut end_time = start_time + note.duration + instrument.release();
+        let mut fade_out = 0.1; // 100ms fade out
+
+        // A new note on the same string cuts this one short, or replaces it altogether
+        // when both start together
+        if let Some(choke) = choke
+            && choke <= start_time
+        {
+            continue;
+        }
+        if let Some(choke) = choke
+            && choke + CHOKE_FADE < end_time
+        {
+            end_time = choke + CHOKE_FADE;
+            fade_out = CHOKE_FADE;
+        }
+
+        let voice = instrument.note(&NoteParams {
+            frequency: frequency as f32,
+            velocity: note.velocity,
+            duration: note.duration,
+            articulations: note.articulations.clone(),
+            technique: note.technique,
+            pick_position: note.pick_position,
+        });
+
+        // Fades may not outlast the note, which matters for zero-length notes
+        let length = end_time - start_time;
+
+        // Add to sequencer - each note plays sequentially
+        sequencer.push(
+            start_time,
+            end_time,
+            Fade::Smooth,
+            f64::min(0.01, length), // 10ms fade in
+            f64::min(fade_out, length),
+            voice,
+        );
+    }
+
+    // Convert sequencer to net
+    let sequencer_id = net.push(Box::new(sequencer));
+
+    // Add final effects
+    let output_id = effects.connect(&mut net, sequencer_id, score.tempo.initial_tempo());
+    net.pipe_output(output_id);
+
+    net
+}
+
+// For each note played on a string, the start of the next note on that string
+fn choke_times(score: &Score) -> Vec<Option<f64>> {
+    let mut order: Vec<usize> = (0..score.notes.len()).collect();
+    order.sort_by(|&a, &b| score.notes[a].start.total_cmp(&score.notes[b].start));
+
+    let mut chokes = vec![None; score.notes.len()];
+    let mut last_on_string: HashMap<usize, usize> = HashMap::new();
+    for index in order {
+        let note = &score.notes[index];
+        if let Some(string) = note.string
+            && let Some(previous) = last_on_string.insert(string, index)
+        {
+            chokes[previous] = Some(note.start);
+        }
+    }
+    chokes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instrument::InstrumentKind;
+
+    // Build the graph of a score on every instrument
+    fn build(score: &str) {
+        let score = Score::parse(score).expect("valid score");
+        for kind in [
+            InstrumentKind::Guitar,
+            InstrumentKind::Karplus,
+            InstrumentKind::Organ,
+        ] {
+            let instrument = kind.build();
+            create_audio_graph(
+                &score,
+                instrument.as_ref(),
+                &Tuning::default(),
+                &EffectsChain::empty(),
+            );
+        }
+    }
+
+    #[test]
+    fn chokes() {
+        let score = Score::parse("shape x32010 q\nshape x32010 0\nshape x32010 q").unwrap();
+        let chokes = choke_times(&score);
+        // Each chord chokes the one before string by string, and the zero-length chord
+        // starts together with the last one
+        for (index, choke) in chokes.iter().enumerate() {
+            let next = score.notes.get(index + 5).map(|note| note.start);
+            assert_eq!(*choke, next);
+        }
+        assert_eq!(score.notes[5].start, score.notes[10].start);
+    }
+
+    #[test]
+    fn same_start_chokes() {
+        build("rest 0.022\nC4 0\nshape 000000 0\nshape 000000 q");
+        build("bar 1\nshape 000000 h\nbar 1\nshape 022100 h");
+    }
+
+    #[test]
+    fn zero_length_notes() {
+        build("rest 0.7\nC4 0\n");
+        build("C4 0\nD4 0\nE4 q");
+    }
+}
@@ -0,0 +1,260 @@
+//! Musical time: tempo changes, time signatures and note lengths, measured in beats.
+//!
+//! A beat is always a quarter note, whatever the time signature.
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+/// Tempo of scores that do not set one, in BPM.
+pub const DEFAULT_TEMPO: f64 = 120.0;
+
+/// A time signature such as 3/4 or 6/8.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeSignature {
+    /// Notes per bar.
+    pub beats: u32,
+    /// Note value of one of those notes, e.g. 4 for quarter notes.
+    pub unit: u32,
+}
+
+impl TimeSignature {
+    pub const COMMON: TimeSignature = TimeSignature { beats: 4, unit: 4 };
+
+    pub fn parse(s: &str) -> Result<TimeSignature, anyhow::Error> {
+        let parsed = s
+            .split_once('/')
+            .and_then(|(n, d)| Some((n.parse::<u32>().ok()?, d.parse::<u32>().ok()?)));
+        match parsed {
+            Some((beats, unit)) if beats > 0 && unit.is_power_of_two() => {
+                Ok(TimeSignature { beats, unit })
+            }
+            _ => bail!("invalid time signature '{s}'"),
+        }
+    }
+
+    /// Length of a bar in quarter-note beats.
+    pub fn bar_beats(&self) -> f64 {
+        self.beats as f64 * 4.0 / self.unit as f64
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TempoChange {
+    beat: f64,
+    // Time of the change, kept up to date with the changes before it
+    seconds: f64,
+    bpm: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SignatureChange {
+    beat: f64,
+    // Bars before the change, counted from zero
+    bar: f64,
+    signature: TimeSignature,
+}
+
+/// Converts between beats and seconds through tempo changes, and finds bars through
+/// time signature changes. Both start at beat 0, which is the start of bar 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TempoMap {
+    tempos: Vec<TempoChange>,
+    signatures: Vec<SignatureChange>,
+}
+
+impl Default for TempoMap {
+    fn default() -> Self {
+        TempoMap::new(DEFAULT_TEMPO)
+    }
+}
+
+impl TempoMap {
+    /// A constant tempo in 4/4.
+    pub fn new(bpm: f64) -> Self {
+        TempoMap {
+            tempos: vec![TempoChange {
+                beat: 0.0,
+                seconds: 0.0,
+                bpm,
+            }],
+            signatures: vec![SignatureChange {
+                beat: 0.0,
+                bar: 0.0,
+                signature: TimeSignature::COMMON,
+            }],
+        }
+    }
+
+    /// Change the tempo from `beat` on, replacing any change at the same beat.
+    pub fn set_tempo(&mut self, beat: f64, bpm: f64) {
+        let index = self.tempos.partition_point(|change| change.beat < beat);
+        match self.tempos.get_mut(index) {
+            Some(change) if change.beat == beat => change.bpm = bpm,
+            _ => self.tempos.insert(
+                index,
+                TempoChange {
+                    beat,
+                    seconds: 0.0,
+                    bpm,
+                },
+            ),
+        }
+        for i in 1..self.tempos.len() {
+            let previous = self.tempos[i - 1];
+            self.tempos[i].seconds =
+                previous.seconds + (self.tempos[i].beat - previous.beat) * 60.0 / previous.bpm;
+        }
+    }
+
+    /// Change the time signature from `beat` on, which should be the start of a bar.
+    pub fn set_time_signature(&mut self, beat: f64, signature: TimeSignature) {
+        let index = self.signatures.partition_point(|change| change.beat < beat);
+        match self.signatures.get_mut(index) {
+            Some(change) if change.beat == beat => change.signature = signature,
+            _ => self.signatures.insert(
+                index,
+                SignatureChange {
+                    beat,
+                    bar: 0.0,
+                    signature,
+                },
+            ),
+        }
+        for i in 1..self.signatures.len() {
+            let previous = self.signatures[i - 1];
+            self.signatures[i].bar = previous.bar
+                + (self.signatures[i].beat - previous.beat) / previous.signature.bar_beats();
+        }
+    }
+
+    /// Tempo in BPM at `beat`.
+    pub fn tempo_at(&self, beat: f64) -> f64 {
+        self.tempo_change(beat).bpm
+    }
+
+    /// Tempo in BPM at the start.
+    pub fn initial_tempo(&self) -> f64 {
+        self.tempos[0].bpm
+    }
+
+    pub fn time_signature_at(&self, beat: f64) -> TimeSignature {
+        let index = self
+            .signatures
+            .partition_point(|change| change.beat <= beat);
+        self.signatures[index.saturating_sub(1)].signature
+    }
+
+    /// Time in seconds of a beat position.
+    pub fn seconds(&self, beat: f64) -> f64 {
+        let change = self.tempo_change(beat);
+        change.seconds + (beat - change.beat) * 60.0 / change.bpm
+    }
+
+    /// Beat position at a time in seconds.
+    pub fn beats(&self, seconds: f64) -> f64 {
+        let index = self
+            .tempos
+            .partition_point(|change| change.seconds <= seconds);
+        let change = self.tempos[index.saturating_sub(1)];
+        change.beat + (seconds - change.seconds) * change.bpm / 60.0
+    }
+
+    /// Beat position where bar `bar` starts, counting bars from 1.
+    pub fn bar_start(&self, bar: u32) -> f64 {
+        let bar = bar.saturating_sub(1) as f64;
+        let index = self.signatures.partition_point(|change| change.bar <= bar);
+        let change = self.signatures[index.saturating_sub(1)];
+        change.beat + (bar - change.bar) * change.signature.bar_beats()
+    }
+
+    fn tempo_change(&self, beat: f64) -> TempoChange {
+        let index = self.tempos.partition_point(|change| change.beat <= beat);
+        self.tempos[index.saturating_sub(1)]
+    }
+}
+
+/// A note length as a fraction of a whole note, written `1/8`, dotted `1/8d` or
+/// triplet `1/8t`. The letters `w`, `h`, `q`, `e` and `s` stand for whole to sixteenth
+/// notes, and `.` also marks a dotted note, as in `q.`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Division {
+    pub numerator: u32,
+    pub denominator: u32,
+    pub modifier: DivisionModifier,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DivisionModifier {
+    Straight,
+    /// One and a half times as long.
+    Dotted,
+    /// Two thirds as long, so three fit in the straight length.
+    Triplet,
+}
+
+impl Division {
+    pub fn parse(s: &str) -> Result<Division, anyhow::Error> {
+        let trimmed = s.trim();
+        let (fraction, modifier) = match trimmed.strip_suffix(['d', '.', 't']) {
+            Some(fraction) if trimmed.ends_with('t') => (fraction, DivisionModifier::Triplet),
+            Some(fraction) => (fraction, DivisionModifier::Dotted),
+            None => (trimmed, DivisionModifier::Straight),
+        };
+        let parsed = match fraction {
+            "w" => Some((1, 1)),
+            "h" => Some((1, 2)),
+            "q" => Some((1, 4)),
+            "e" => Some((1, 8)),
+            "s" => Some((1, 16)),
+            _ => fraction
+                .split_once('/')
+                .and_then(|(n, d)| Some((n.parse::<u32>().ok()?, d.parse::<u32>().ok()?))),
+        };
+        match parsed {
+            Some((numerator, denominator)) if numerator > 0 && denominator > 0 => Ok(Division {
+                numerator,
+                denominator,
+                modifier,
+            }),
+            _ => bail!("invalid note length '{s}'; expected e.g. q, e., 1/4, 1/8d or 1/16t"),
+        }
+    }
+
+    /// Length in quarter-note beats.
+    pub fn beats(&self) -> f64 {
+        let straight = 4.0 * self.numerator as f64 / self.denominator as f64;
+        match self.modifier {
+            DivisionModifier::Straight => straight,
+            DivisionModifier::Dotted => straight * 1.5,
+            DivisionModifier::Triplet => straight * 2.0 / 3.0,
+        }
+    }
+
+    /// Length in seconds at `tempo` BPM.
+    pub fn seconds(&self, tempo: f64) -> f64 {
+        self.beats() * 60.0 / tempo
+    }
+}
+
+impl TryFrom<String> for Division {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Division::parse(&s)
+    }
+}
+
+impl From<Division> for String {
+    fn from(division: Division) -> String {
+        let modifier = match division.modifier {
+            DivisionModifier::Straight => "",
+            DivisionModifier::Dotted => "d",
+            DivisionModifier::Triplet => "t",
+        };
+        format!(
+            "{}/{}{}",
+            division.numerator, division.denominator, modifier
+        )
+    }
+}
@@ -0,0 +1,457 @@
+//! Tunings that turn MIDI note numbers into frequencies: equal temperament, historical
+//! temperaments and Scala scale (`.scl`) and keyboard mapping (`.kbm`) files.
+
+use anyhow::{Context, bail};
+use std::path::Path;
+
+// Middle C, where built-in scales start unless moved with a root
+const MIDDLE_C: i32 = 60;
+
+// A4 and its standard frequency
+const REFERENCE_KEY: i32 = 69;
+const REFERENCE_FREQUENCY: f64 = 440.0;
+
+/// A scale mapped onto the MIDI keyboard. Fractional pitches are offset from the nearest
+/// key by hundredths of a semitone, so `60.5` lies 50 cents above whatever key 60 plays.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tuning {
+    /// Cents of each scale degree above the first, ending with the period, which is
+    /// usually the octave at 1200 cents.
+    pub degrees: Vec<f64>,
+    pub mapping: KeyboardMapping,
+}
+
+/// Which keys play which scale degrees and how the scale is anchored in frequency,
+/// as in a Scala `.kbm` file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyboardMapping {
+    /// Key that plays the first scale degree.
+    pub middle_key: i32,
+    /// Key whose frequency is given, which need not be on the scale.
+    pub reference_key: i32,
+    pub reference_frequency: f64,
+    /// Scale degree of each key in a repeating pattern that starts at the middle key,
+    /// with `None` for keys that play nothing. Empty maps each key to the next degree.
+    pub keys: Vec<Option<i32>>,
+    /// Scale degree reached when the pattern of keys repeats, or 0 for the period.
+    pub octave_degree: i32,
+}
+
+impl Default for KeyboardMapping {
+    /// Every key on the next degree, starting at middle C, with A4 at 440 Hz.
+    fn default() -> Self {
+        KeyboardMapping {
+            middle_key: MIDDLE_C,
+            reference_key: REFERENCE_KEY,
+            reference_frequency: REFERENCE_FREQUENCY,
+            keys: Vec::new(),
+            octave_degree: 0,
+        }
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning::equal()
+    }
+}
+
+impl Tuning {
+    /// Twelve-tone equal temperament with A4 at 440 Hz.
+    pub fn equal() -> Self {
+        Tuning::from_cents((1..=12).map(|step| step as f64 * 100.0).collect())
+    }
+
+    /// Five-limit just intonation, in tune for the keys around the root.
+    pub fn just() -> Self {
+        Tuning::from_ratios(&[
+            16.0 / 15.0,
+            9.0 / 8.0,
+            6.0 / 5.0,
+            5.0 / 4.0,
+            4.0 / 3.0,
+            45.0 / 32.0,
+            3.0 / 2.0,
+            8.0 / 5.0,
+            5.0 / 3.0,
+            9.0 / 5.0,
+            15.0 / 8.0,
+            2.0,
+        ])
+    }
+
+    /// Pythagorean tuning, built from pure fifths.
+    pub fn pythagorean() -> Self {
+        Tuning::from_ratios(&[
+            256.0 / 243.0,
+            9.0 / 8.0,
+            32.0 / 27.0,
+            81.0 / 64.0,
+            4.0 / 3.0,
+            729.0 / 512.0,
+            3.0 / 2.0,
+            128.0 / 81.0,
+            27.0 / 16.0,
+            16.0 / 9.0,
+            243.0 / 128.0,
+            2.0,
+        ])
+    }
+
+    /// Quarter-comma meantone, with pure major thirds.
+    pub fn meantone() -> Self {
+        // Fifths narrowed by a quarter of the syntonic comma, stacked from Eb to G#
+        let fifth = 1200.0 * (5.0f64.powf(0.25)).log2();
+        let mut degrees: Vec<f64> = (-3..=8)
+            .map(|fifths| (fifths as f64 * fifth).rem_euclid(1200.0))
+            .filter(|&cents| cents > 0.0)
+            .collect();
+        degrees.sort_by(f64::total_cmp);
+        degrees.push(1200.0);
+        Tuning::from_cents(degrees)
+    }
+
+    /// A built-in tuning by name: `equal`, `just`, `pythagorean` or `meantone`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "equal" | "12tet" | "12-tet" => Some(Tuning::equal()),
+            "just" => Some(Tuning::just()),
+            "pythagorean" => Some(Tuning::pythagorean()),
+            "meantone" => Some(Tuning::meantone()),
+            _ => None,
+        }
+    }
+
+    /// A scale of degrees in cents above the first, ending with the period, on the
+    /// default keyboard mapping.
+    pub fn from_cents(degrees: Vec<f64>) -> Self {
+        Tuning {
+            degrees,
+            mapping: KeyboardMapping::default(),
+        }
+    }
+
+    fn from_ratios(ratios: &[f64]) -> Self {
+        Tuning::from_cents(ratios.iter().map(|ratio| 1200.0 * ratio.log2()).collect())
+    }
+
+    pub fn load_scala(path: &Path) -> Result<Self, anyhow::Error> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read scale {}", path.display()))?;
+        Self::parse_scala(&text).with_context(|| format!("Invalid scale {}", path.display()))
+    }
+
+    /// Parse a Scala `.scl` scale: a description line, the number of notes, then one
+    /// pitch per line, in cents when it has a decimal point and as a ratio otherwise.
+    /// Lines starting with `!` are comments.
+    pub fn parse_scala(text: &str) -> Result<Self, anyhow::Error> {
+        // The description is the first line that is not a comment, and may be blank
+        let mut lines = text.lines().filter(|line| !line.starts_with('!'));
+        lines.next().context("missing description")?;
+        let mut lines = lines.filter_map(|line| line.split_whitespace().next());
+        let count: usize = lines
+            .next()
+            .context("missing number of notes")?
+            .parse()
+            .context("invalid number of notes")?;
+        let degrees = lines
+            .take(count)
+            .map(parse_scala_pitch)
+            .collect::<Result<Vec<_>, _>>()?;
+        if degrees.len() != count {
+            bail!("expected {count} notes, found {}", degrees.len());
+        }
+        if count == 0 {
+            bail!("the scale has no notes");
+        }
+        Ok(Tuning::from_cents(degrees))
+    }
+
+    pub fn with_mapping(mut self, mapping: KeyboardMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    /// Set the frequency of the reference key, A4 unless the keyboard mapping moved it.
+    pub fn with_reference(mut self, frequency: f64) -> Self {
+        self.mapping.reference_frequency = frequency;
+        self
+    }
+
+    /// Start the scale on another key, e.g. 62 for a just scale in D. The reference key
+    /// keeps its frequency.
+    pub fn with_root(mut self, key: i32) -> Self {
+        self.mapping.middle_key = key;
+        self
+    }
+
+    /// Frequency in Hz of a MIDI pitch, or `None` for a key the mapping leaves unused.
+    pub fn frequency(&self, pitch: f64) -> Option<f64> {
+        let key = pitch.round();
+        let cents = self.cents(key as i32)? + (pitch - key) * 100.0;
+        let reference = self.cents(self.mapping.reference_key).unwrap_or_else(|| {
+            // An unmapped reference key still sits at its place in equal temperament
+            100.0 * (self.mapping.reference_key - self.mapping.middle_key) as f64
+        });
+        Some(self.mapping.reference_frequency * ((cents - reference) / 1200.0).exp2())
+    }
+
+    // Cents of a key above the middle key
+    fn cents(&self, key: i32) -> Option<f64> {
+        let offset = key - self.mapping.middle_key;
+        let keys = &self.mapping.keys;
+        if keys.is_empty() {
+            return Some(self.degree_cents(offset));
+        }
+        let size = keys.len() as i32;
+        let degree = keys[offset.rem_euclid(size) as usize]?;
+        let repeats = offset.div_euclid(size) as f64;
+        // An octave degree of 0 stands for the period of the scale
+        let octave = match self.mapping.octave_degree {
+            0 => self.degrees.len() as i32,
+            degree => degree,
+        };
+        Some(repeats * self.degree_cents(octave) + self.degree_cents(degree))
+    }
+
+    // Cents of any scale degree, counting on through further periods
+    fn degree_cents(&self, degree: i32) -> f64 {
+        let size = self.degrees.len() as i32;
+        let period = self.degrees[self.degrees.len() - 1];
+        let step = degree.rem_euclid(size);
+        let base = if step == 0 {
+            0.0
+        } else {
+            self.degrees[step as usize - 1]
+        };
+        degree.div_euclid(size) as f64 * period + base
+    }
+}
+
+impl KeyboardMapping {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read keyboard mapping {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid keyboard mapping {}", path.display()))
+    }
+
+    /// Parse a Scala `.kbm` keyboard mapping: the pattern size, the first and last keys to
+    /// retune, the middle key, the reference key and its frequency, the octave degree and
+    /// then the degree of each key in the pattern, or `x` for none. Keys outside the first
+    /// and last ones keep the mapping too.
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let mut lines = scala_lines(text);
+        let mut field = |name: &str| lines.next().with_context(|| format!("missing {name}"));
+        let size: usize = field("pattern size")?
+            .parse()
+            .context("invalid pattern size")?;
+        field("first key")?;
+        field("last key")?;
+        let middle_key = parse_key(field("middle key")?)?;
+        let reference_key = parse_key(field("reference key")?)?;
+        let reference_frequency = match field("reference frequency")?.parse::<f64>() {
+            Ok(hz) if hz.is_finite() && hz > 0.0 => hz,
+            _ => bail!("invalid reference frequency"),
+        };
+        let octave_degree: i32 = field("octave degree")?
+            .parse()
+            .context("invalid octave degree")?;
+
+        // Keys left out at the end of the pattern play nothing
+        let mut keys = vec![None; size];
+        for (key, line) in keys.iter_mut().zip(lines) {
+            if !line.eq_ignore_ascii_case("x") {
+                *key = Some(
+                    line.parse()
+                        .with_context(|| format!("invalid scale degree '{line}'"))?,
+                );
+            }
+        }
+        Ok(KeyboardMapping {
+            middle_key,
+            reference_key,
+            reference_frequency,
+            keys,
+            octave_degree,
+        })
+    }
+}
+
+// The first word of every line that is not blank or a comment
+fn scala_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .filter(|line| !line.starts_with('!'))
+        .filter_map(|line| line.split_whitespace().next())
+}
+
+fn parse_scala_pitch(s: &str) -> Result<f64, anyhow::Error> {
+    let cents = if s.contains('.') {
+        s.parse::<f64>().ok()
+    } else {
+        let (numerator, denominator) = s.split_once('/').unwrap_or((s, "1"));
+        match (numerator.parse::<f64>(), denominator.parse::<f64>()) {
+            (Ok(n), Ok(d)) if n > 0.0 && d > 0.0 => Some(1200.0 * (n / d).log2()),
+            _ => None,
+        }
+    };
+    cents
+        .filter(|cents| cents.is_finite())
+        .with_context(|| format!("invalid pitch '{s}'"))
+}
+
+fn parse_key(s: &str) -> Result<i32, anyhow::Error> {
+    s.parse::<i32>()
+        .ok()
+        .filter(|key| (0..=127).contains(key))
+        .with_context(|| format!("invalid key '{s}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The quarter-comma meantone example from the Scala file format documentation
+    const MEANQUAR: &str = "! meanquar.scl
+!
+1/4-comma meantone scale. Pietro Aaron's temperament (1523)
+ 12
+!
+ 76.04900
+ 193.15686
+ 310.26471
+ 5/4
+ 503.42157
+ 579.47057
+ 696.57843
+ 25/16
+ 889.73529
+ 1006.84314
+ 1082.89214
+ 2/1
+";
+
+    // White keys only, on a seven-note scale starting at middle C
+    const WHITE_KEYS: &str = "! white.kbm
+! Size of map
+12
+! First and last MIDI note to retune
+0
+127
+! Middle note
+60
+! Reference note and frequency
+69
+440.0
+! Scale degree of the formal octave
+7
+! Mapping
+0
+x
+1
+x
+2
+3
+x
+4
+x
+5
+x
+6
+";
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{actual} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn equal_temperament() {
+        let tuning = Tuning::equal();
+        assert_close(tuning.frequency(69.0).unwrap(), 440.0);
+        assert_close(tuning.frequency(60.0).unwrap(), 261.6256);
+        assert_close(tuning.frequency(57.0).unwrap(), 220.0);
+        // Fractions of a key are cents above it
+        assert_close(
+            tuning.frequency(69.5).unwrap(),
+            440.0 * 2f64.powf(0.5 / 12.0),
+        );
+    }
+
+    #[test]
+    fn just_intonation_keeps_the_reference() {
+        let tuning = Tuning::just();
+        assert_close(tuning.frequency(69.0).unwrap(), 440.0);
+        assert_close(tuning.frequency(60.0).unwrap(), 264.0);
+        assert_close(tuning.frequency(64.0).unwrap(), 330.0);
+        assert_close(tuning.frequency(72.0).unwrap(), 528.0);
+    }
+
+    #[test]
+    fn scala_scale() {
+        let tuning = Tuning::parse_scala(MEANQUAR).unwrap();
+        assert_eq!(tuning.degrees.len(), 12);
+        assert_close(tuning.degrees[3], 1200.0 * 1.25f64.log2());
+        for (parsed, built_in) in tuning.degrees.iter().zip(Tuning::meantone().degrees) {
+            assert_close(*parsed, built_in);
+        }
+        assert_close(tuning.frequency(69.0).unwrap(), 440.0);
+    }
+
+    #[test]
+    fn invalid_scala_scales() {
+        assert!(Tuning::parse_scala("").is_err());
+        assert!(Tuning::parse_scala("too short\n 3\n 100.0\n 2/1\n").is_err());
+        assert!(Tuning::parse_scala("bad ratio\n 1\n 3/0\n").is_err());
+        assert!(Tuning::parse_scala("empty\n 0\n").is_err());
+    }
+
+    #[test]
+    fn keyboard_mapping() {
+        let mapping = KeyboardMapping::parse(WHITE_KEYS).unwrap();
+        assert_eq!(mapping.middle_key, 60);
+        assert_eq!(mapping.reference_key, 69);
+        assert_eq!(mapping.octave_degree, 7);
+        assert_eq!(
+            mapping.keys,
+            [
+                Some(0),
+                None,
+                Some(1),
+                None,
+                Some(2),
+                Some(3),
+                None,
+                Some(4),
+                None,
+                Some(5),
+                None,
+                Some(6)
+            ]
+        );
+
+        // C major in just intonation on the white keys
+        let major = [
+            9.0 / 8.0,
+            5.0 / 4.0,
+            4.0 / 3.0,
+            3.0 / 2.0,
+            5.0 / 3.0,
+            15.0 / 8.0,
+            2.0,
+        ];
+        let tuning = Tuning::from_ratios(&major).with_mapping(mapping);
+        assert_close(tuning.frequency(69.0).unwrap(), 440.0);
+        assert_close(tuning.frequency(60.0).unwrap(), 264.0);
+        assert_close(tuning.frequency(74.0).unwrap(), 594.0);
+        assert_eq!(tuning.frequency(61.0), None);
+    }
+
+    #[test]
+    fn invalid_keyboard_mappings() {
+        assert!(KeyboardMapping::parse("12\n0\n127\n60\n69\n").is_err());
+        assert!(KeyboardMapping::parse("1\n0\n127\n60\n69\n-440\n0\n0\n").is_err());
+        assert!(KeyboardMapping::parse("1\n0\n127\n200\n69\n440\n0\n0\n").is_err());
+    }
+}